@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::invalid;
+use crate::partial::{merge, read_partial, write_partial};
+use crate::{Config, Error, ParseError, ParseErrorKind, Policy, Results, Window, aggregate};
+
+// The file is aggregated one newline-aligned segment at a time, and the state so far is saved after each
+// segment, so that a run can pick up where a crashed one left off.
+//
+// Layout, all little-endian:
+//   "BRCK" version:u8 offset:u64 flags:u8 (1: variance, 2: percentiles, 4: hourly, 8: daily windows)
+//   decimals:u8 (255 without Schema::decimals) delimiter:u8
+//   station:u64 measurement:u64 timestamp:u64 (the Schema::columns, u64::MAX where there are none)
+//   partial (see partial.rs)
+//   first rejected: n:u32, then per line offset:u64 kind:u8
+
+const MAGIC: &[u8; 4] = b"BRCK";
+const VERSION: u8 = 1;
+const VARIANCE: u8 = 1;
+const PERCENTILES: u8 = 2;
+const HOURLY: u8 = 4;
+const DAILY: u8 = 8;
+const OPTIONS_LEN: usize = 27;
+
+pub(crate) fn checkpoint_aggregate(
+    file: &File,
+    checkpoint: &Path,
+    every: u64,
+    resume: bool,
+    config: &Config,
+) -> Result<Results, Error> {
+    let len = file.metadata()?.len();
+    let (mut offset, mut results) = if resume {
+        load(file, checkpoint, len, config).map_err(Error::Checkpoint)?
+    } else {
+        (0, Results::default())
+    };
+
+    while offset < len {
+        let end = segment_end(file, offset + every.max(1), len)?;
+        add_segment(file, offset, end, &mut results, config)?;
+        offset = end;
+        save(checkpoint, offset, &results, config).map_err(Error::Checkpoint)?;
+    }
+
+    match std::fs::remove_file(checkpoint) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::Checkpoint(err)),
+        _ => Ok(results),
+    }
+}
+
+// Aggregate the lines in start..end of the file into results, with offsets relative to the whole file
+pub(crate) fn add_segment(
+    mut file: &File,
+    start: u64,
+    end: u64,
+    results: &mut Results,
+    config: &Config,
+) -> Result<(), Error> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut part = aggregate(file.take(end - start), config).map_err(|err| match err {
+        Error::Parse(err) => Error::Parse(ParseError {
+            offset: err.offset + start,
+            ..err
+        }),
+        err => err,
+    })?;
+    for err in &mut part.rejected.first {
+        err.offset += start;
+    }
+    let mut first = std::mem::take(&mut results.rejected.first);
+    first.append(&mut part.rejected.first);
+    first.truncate(limit(config.policy));
+    *results = merge([std::mem::take(results), part]).map_err(Error::Checkpoint)?;
+    results.rejected.first = first;
+    Ok(())
+}
+
+// Just after the first newline at or after target, or the end of the file
+fn segment_end(file: &File, target: u64, len: u64) -> io::Result<u64> {
+    if target >= len {
+        return Ok(len);
+    }
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(target))?;
+    let skipped = reader.skip_until(b'\n')? as u64;
+    Ok((target + skipped).min(len))
+}
+
+fn save(checkpoint: &Path, offset: u64, results: &Results, config: &Config) -> io::Result<()> {
+    // written next to it and renamed over it, so that a crash never leaves a half-written checkpoint
+    let mut tmp = checkpoint.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let file = File::create(&tmp)?;
+    let mut w = BufWriter::new(&file);
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    w.write_all(&offset.to_le_bytes())?;
+    w.write_all(&options(config))?;
+    write_partial(&mut w, results)?;
+    w.write_all(&(results.rejected.first.len() as u32).to_le_bytes())?;
+    for err in &results.rejected.first {
+        w.write_all(&err.offset.to_le_bytes())?;
+        w.write_all(&[err.kind as u8])?;
+    }
+    w.flush()?;
+    drop(w);
+    file.sync_all()?;
+    std::fs::rename(&tmp, checkpoint)
+}
+
+// Without a checkpoint to resume from, start from the beginning
+fn load(file: &File, checkpoint: &Path, len: u64, config: &Config) -> io::Result<(u64, Results)> {
+    let mut r = match File::open(checkpoint) {
+        Ok(r) => BufReader::new(r),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, Results::default())),
+        Err(err) => return Err(err),
+    };
+    let mut header = [0; 13 + OPTIONS_LEN];
+    r.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a checkpoint file"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid(format!("unsupported checkpoint version {}", header[4])));
+    }
+    let offset = u64::from_le_bytes(header[5..13].try_into().unwrap());
+    if header[13..] != options(config) {
+        return Err(invalid(
+            "checkpoint was made with different --variance, --percentiles, --window, --decimals, --delimiter or \
+             --columns options",
+        ));
+    }
+    // segments end just after a newline, which is a cheap check that the checkpoint is for this input
+    if offset > len || 0 < offset && offset < len && byte_at(file, offset - 1)? != b'\n' {
+        return Err(invalid("checkpoint doesn't match the input file"));
+    }
+
+    let mut results = read_partial(&mut r)?;
+    let mut n = [0; 4];
+    r.read_exact(&mut n)?;
+    for _ in 0..u32::from_le_bytes(n) {
+        let mut entry = [0; 9];
+        r.read_exact(&mut entry)?;
+        let kind = match entry[8] {
+            0 => ParseErrorKind::MissingSeparator,
+            1 => ParseErrorKind::InvalidMeasurement,
+            2 => ParseErrorKind::InvalidTimestamp,
+            3 => ParseErrorKind::LineTooLong,
+            4 => ParseErrorKind::MissingColumn,
+            5 => ParseErrorKind::TooManyDecimals,
+            6 => ParseErrorKind::InvalidUtf8,
+            kind => return Err(invalid(format!("unknown error kind {kind}"))),
+        };
+        results.rejected.first.push(ParseError {
+            offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+            kind,
+            file: None,
+        });
+    }
+    Ok((offset, results))
+}
+
+fn byte_at(mut file: &File, offset: u64) -> io::Result<u8> {
+    let mut byte = [0];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+// Everything in the config that changes what the results are, as saved in the header
+fn options(config: &Config) -> [u8; OPTIONS_LEN] {
+    let schema = &config.schema;
+    let column = |column: Option<usize>| column.map_or(u64::MAX, |i| i as u64).to_le_bytes();
+    let mut options = [0; OPTIONS_LEN];
+    options[0] = flags(config);
+    options[1] = schema.decimals.unwrap_or(u8::MAX);
+    options[2] = schema.delimiter;
+    options[3..11].copy_from_slice(&column(schema.columns.map(|columns| columns.station)));
+    options[11..19].copy_from_slice(&column(schema.columns.map(|columns| columns.measurement)));
+    options[19..].copy_from_slice(&column(schema.columns.and_then(|columns| columns.timestamp)));
+    options
+}
+
+fn flags(config: &Config) -> u8 {
+    let window = match config.window {
+        None => 0,
+        Some(Window::Hour) => HOURLY,
+        Some(Window::Day) => DAILY,
+    };
+    (if config.variance { VARIANCE } else { 0 }) | (if config.percentiles { PERCENTILES } else { 0 }) | window
+}
+
+fn limit(policy: Policy) -> usize {
+    match policy {
+        Policy::Collect(limit) => limit,
+        _ => 0,
+    }
+}
@@ -0,0 +1,455 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use brc::input::is_compressed;
+use brc::output::Format;
+use brc::{Columns, Config, Policy, Window};
+
+pub(crate) const USAGE: &str = "\
+Aggregate min/mean/max temperatures per weather station
+
+Usage: brc [aggregate] [OPTIONS] [PATH]...
+       brc validate [OPTIONS] [PATH]...
+       brc merge [OPTIONS] <PARTIAL>...
+       brc generate [--rows <N>] [--seed <N>] [OUTPUT]
+
+Commands:
+  aggregate  Aggregate a measurements file and print the results (default)
+  validate   Check a measurements file for malformed lines
+  merge      Merge partial aggregates written with --partial, and print the results
+  generate   Generate a random measurements file
+
+PATH defaults to measurements.txt, and '-' reads from stdin. Several paths are aggregated together, as if
+they were concatenated. gzip and zstd compressed input is decompressed on the fly.
+OUTPUT defaults to measurements.txt, which must not exist yet.
+
+Options:
+  -f, --format <FORMAT>        Output format: text, json, csv or tsv [default: text]
+  -o, --output <FILE>          Write the results to FILE instead of stdout
+      --on-error <POLICY>      What to do with malformed lines: fail, skip or collect [default: fail]
+      --variance               Also compute the variance and standard deviation per station
+      --percentiles            Also compute the median, 95th and 99th percentile per station
+      --histogram              Output every station's full histogram instead (implies --percentiles)
+      --window <WINDOW>        Input lines are station;timestamp;temperature, aggregated per hour or day
+                               (UTC). Timestamps are unix seconds or ISO-8601 like 2024-06-01T13:45:00Z
+      --delimiter <CHAR>       Byte between the columns of input lines, or '\t' [default: ;]
+      --columns <LIST>         What each column of input lines is, by position: station, temperature,
+                               timestamp (with --window) or _ for columns to ignore, like _,station,temperature
+      --decimals <N>           Accept integers, a leading '+' and up to N decimals in measurements, instead
+                               of exactly one decimal, and output results with N decimals
+      --partial <FILE>         Write a partial aggregate to FILE instead of printing results, for merge
+      --mmap                   Memory-map the input file instead of reading it through a reader thread
+      --shards                 Treat each PATH as a directory or glob ('shards/*.txt') of shard files, and
+                               parse whole files in parallel instead of through a single reader thread
+      --checkpoint <FILE>      Save the state so far to FILE every --checkpoint-every bytes of the input file
+      --checkpoint-every <BYTES>
+                               How much input to aggregate between checkpoints [default: 1000000000]
+      --resume                 Continue from the --checkpoint FILE of an interrupted run, if there is one
+      --follow                 Keep polling the input file for appended lines, and output updated results
+                               (replacing the --output FILE) whenever there are new ones
+      --interval <SECS>        How often --follow polls the input file [default: 10]
+      --threads <N>            Total number of threads, including the reader [default: all cores]
+      --block-size <BYTES>     Size of the blocks handed from the reader to the parsers [default: 50000]
+      --blocks-per-thread <N>  Blocks in flight per parser thread [default: 3]
+      --max-line-len <BYTES>   Longest valid line, longer ones are malformed [default: 4096]
+      --rows <N>               Number of lines to generate [default: 1000000000]
+      --seed <N>               Seed for generate, for reproducible output
+  -h, --help                   Print help
+  -V, --version                Print version
+";
+
+// how many invalid lines are reported with --on-error=collect, and by validate
+pub(crate) const COLLECT_LIMIT: usize = 10;
+
+pub(crate) enum Command {
+    Aggregate(Input, Output),
+    Validate(Input),
+    Merge(Vec<PathBuf>, Output),
+    Generate(Generate),
+    Help,
+    Version,
+}
+
+pub(crate) struct Input {
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) mmap: bool,
+    pub(crate) shards: bool,
+    pub(crate) checkpoint: Option<Checkpoint>,
+    pub(crate) follow: Option<Duration>,
+    pub(crate) config: Config,
+}
+
+pub(crate) struct Output {
+    pub(crate) format: Format,
+    pub(crate) histogram: bool,
+    pub(crate) partial: Option<PathBuf>,
+    pub(crate) path: Option<PathBuf>,
+}
+
+pub(crate) struct Checkpoint {
+    pub(crate) path: PathBuf,
+    pub(crate) every: u64,
+    pub(crate) resume: bool,
+}
+
+pub(crate) struct Generate {
+    pub(crate) path: PathBuf,
+    pub(crate) rows: usize,
+    pub(crate) seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Subcommand {
+    Aggregate,
+    Validate,
+    Merge,
+    Generate,
+}
+
+pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("aggregate") => Some(Subcommand::Aggregate),
+        Some("validate") => Some(Subcommand::Validate),
+        Some("merge") => Some(Subcommand::Merge),
+        Some("generate") => Some(Subcommand::Generate),
+        _ => None,
+    };
+    if subcommand.is_some() {
+        args.next();
+    }
+    // no subcommand is the same as aggregate, so that `brc measurements.txt` keeps working
+    let subcommand = subcommand.unwrap_or(Subcommand::Aggregate);
+
+    let mut paths = Vec::new();
+    let mut mmap = false;
+    let mut shards = false;
+    let mut format = Format::Text;
+    let mut histogram = false;
+    let mut partial = None;
+    let mut checkpoint = None;
+    let mut checkpoint_every = 1_000_000_000;
+    let mut resume = false;
+    let mut follow = false;
+    let mut interval = 10;
+    let mut output = None;
+    let mut config = Config::default();
+    let mut rows = 1_000_000_000;
+    let mut seed = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            paths.push(PathBuf::from(arg));
+            continue;
+        }
+        // both --option=value and --option value
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) => (option.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{option} needs a value"))
+        };
+        match (subcommand, option.as_str()) {
+            (_, "-h" | "--help") => return Ok(Command::Help),
+            (_, "-V" | "--version") => return Ok(Command::Version),
+            (Subcommand::Aggregate | Subcommand::Merge, "-f" | "--format") => format = value()?.parse()?,
+            (Subcommand::Aggregate | Subcommand::Merge, "-o" | "--output") => output = Some(PathBuf::from(value()?)),
+            (Subcommand::Aggregate | Subcommand::Merge, "--histogram") => histogram = true,
+            (Subcommand::Aggregate | Subcommand::Merge, "--partial") => partial = Some(PathBuf::from(value()?)),
+            (Subcommand::Aggregate | Subcommand::Validate, "--mmap") => mmap = true,
+            (Subcommand::Aggregate | Subcommand::Validate, "--shards") => shards = true,
+            (Subcommand::Aggregate, "--checkpoint") => checkpoint = Some(PathBuf::from(value()?)),
+            (Subcommand::Aggregate, "--checkpoint-every") => checkpoint_every = number(&option, &value()?)?,
+            (Subcommand::Aggregate, "--resume") => resume = true,
+            (Subcommand::Aggregate, "--follow") => follow = true,
+            (Subcommand::Aggregate, "--interval") => interval = number(&option, &value()?)?,
+            (Subcommand::Aggregate | Subcommand::Validate, "--window") => {
+                config.window = match value()?.as_str() {
+                    "hour" => Some(Window::Hour),
+                    "day" => Some(Window::Day),
+                    other => return Err(format!("unknown window '{other}', expected hour or day")),
+                }
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--delimiter") => {
+                // a byte that can be part of a measurement or of a line end would split lines wrongly
+                config.schema.delimiter = match value()?.as_bytes() {
+                    b"\\t" => b'\t',
+                    &[byte] if !matches!(byte, b'0'..=b'9' | b'.' | b'-' | b'+' | b'\r' | b'\n') => byte,
+                    _ => {
+                        return Err(format!("{option} must be a single ASCII character other than a digit, '.', '-' or '+'"));
+                    }
+                }
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--decimals") => {
+                config.schema.decimals = match value()?.parse() {
+                    Ok(decimals) if decimals <= 6 => Some(decimals),
+                    _ => return Err(format!("{option} must be a number from 0 to 6")),
+                }
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--columns") => {
+                config.schema.columns = Some(columns(&value()?)?)
+            }
+            (Subcommand::Aggregate, "--variance") => config.variance = true,
+            (Subcommand::Aggregate, "--percentiles") => config.percentiles = true,
+            (Subcommand::Aggregate, "--on-error") => {
+                config.policy = match value()?.as_str() {
+                    "fail" => Policy::FailFast,
+                    "skip" => Policy::Skip,
+                    "collect" => Policy::Collect(COLLECT_LIMIT),
+                    other => return Err(format!("unknown policy '{other}', expected fail, skip or collect")),
+                }
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--threads") => {
+                config.threads = number(&option, &value()?)?
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--block-size") => {
+                config.block_size = number(&option, &value()?)?
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--blocks-per-thread") => {
+                config.blocks_per_thread = number(&option, &value()?)?
+            }
+            (Subcommand::Aggregate | Subcommand::Validate, "--max-line-len") => {
+                config.max_line_len = number(&option, &value()?)?
+            }
+            (Subcommand::Generate, "--rows") => rows = number(&option, &value()?)?,
+            (Subcommand::Generate, "--seed") => {
+                seed = Some(value()?.parse().map_err(|_| format!("{option} must be a number"))?)
+            }
+            _ => return Err(format!("unexpected option '{option}'")),
+        }
+    }
+
+    if paths.is_empty() && subcommand != Subcommand::Merge {
+        paths.push("measurements.txt".into());
+    }
+    if mmap && (paths.len() > 1 || paths[0] == Path::new("-")) {
+        return Err("--mmap needs a single input file".into());
+    }
+    if shards && (mmap || paths.iter().any(|path| path == Path::new("-"))) {
+        return Err("--shards can't be combined with --mmap or stdin".into());
+    }
+    if checkpoint.is_some() && (mmap || shards || paths.len() > 1 || paths[0] == Path::new("-")) {
+        return Err("--checkpoint needs a single input file, and can't be combined with --mmap or --shards".into());
+    }
+    if follow && (mmap || shards || checkpoint.is_some() || paths.len() > 1 || paths[0] == Path::new("-")) {
+        return Err("--follow needs a single input file, and can't be combined with --mmap, --shards or --checkpoint".into());
+    }
+    // these read the file as it is, the others go through `decompress`
+    if (mmap || checkpoint.is_some() || follow) && is_compressed(&paths[0]).unwrap_or(false) {
+        return Err("--mmap, --checkpoint and --follow need an uncompressed input file".into());
+    }
+    if let Some(columns) = config.schema.columns
+        && columns.timestamp.is_some() != config.window.is_some()
+    {
+        return Err("--columns needs a timestamp column exactly when there is a --window".into());
+    }
+    if histogram && config.window.is_some() {
+        return Err("--histogram can't be combined with --window".into());
+    }
+    // a line has to fit in a block along with its newline
+    if config.block_size <= config.max_line_len {
+        return Err("--block-size must be larger than --max-line-len".into());
+    }
+    if resume && checkpoint.is_none() {
+        return Err("--resume needs a --checkpoint file".into());
+    }
+    let checkpoint = checkpoint.map(|path| Checkpoint {
+        path,
+        every: checkpoint_every as u64,
+        resume,
+    });
+    let follow = follow.then(|| Duration::from_secs(interval as u64));
+    Ok(match subcommand {
+        Subcommand::Aggregate => {
+            config.percentiles |= histogram;
+            let output = Output {
+                format,
+                histogram,
+                partial,
+                path: output,
+            };
+            let input = Input {
+                paths,
+                mmap,
+                shards,
+                checkpoint,
+                follow,
+                config,
+            };
+            Command::Aggregate(input, output)
+        }
+        Subcommand::Validate => {
+            config.policy = Policy::Collect(COLLECT_LIMIT);
+            Command::Validate(Input {
+                paths,
+                mmap,
+                shards,
+                checkpoint,
+                follow,
+                config,
+            })
+        }
+        Subcommand::Merge => {
+            if paths.is_empty() {
+                return Err("merge needs at least one partial".into());
+            }
+            let output = Output {
+                format,
+                histogram,
+                partial,
+                path: output,
+            };
+            Command::Merge(paths, output)
+        }
+        Subcommand::Generate => {
+            let [path] = <[_; 1]>::try_from(paths).map_err(|_| "generate writes a single file")?;
+            Command::Generate(Generate { path, rows, seed })
+        }
+    })
+}
+
+fn columns(list: &str) -> Result<Columns, String> {
+    let (mut station, mut measurement, mut timestamp) = (None, None, None);
+    for (i, column) in list.split(',').enumerate() {
+        let slot = match column {
+            "station" => &mut station,
+            "temperature" => &mut measurement,
+            "timestamp" => &mut timestamp,
+            "_" => continue,
+            other => return Err(format!("unknown column '{other}', expected station, temperature, timestamp or _")),
+        };
+        if slot.replace(i).is_some() {
+            return Err(format!("--columns has more than one {column} column"));
+        }
+    }
+    match (station, measurement) {
+        (Some(station), Some(measurement)) => Ok(Columns {
+            station,
+            measurement,
+            timestamp,
+        }),
+        _ => Err("--columns needs a station and a temperature column".into()),
+    }
+}
+
+fn number(option: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{option} must be a positive number")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    fn aggregate(args: &str) -> (Input, Output) {
+        match parse_str(args) {
+            Ok(Command::Aggregate(input, output)) => (input, output),
+            _ => panic!("'{args}' isn't a valid aggregate command"),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_str(args) {
+            Err(err) => err,
+            Ok(_) => panic!("'{args}' should be rejected"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let (input, output) = aggregate("");
+        assert_eq!(input.paths, [Path::new("measurements.txt")]);
+        assert!(!input.mmap && !input.shards && input.checkpoint.is_none() && input.follow.is_none());
+        assert_eq!(input.config.policy, Policy::FailFast);
+        assert_eq!(input.config.block_size, Config::default().block_size);
+        assert_eq!(input.config.max_line_len, Config::default().max_line_len);
+        assert_eq!(output.format, Format::Text);
+        assert!(output.path.is_none() && output.partial.is_none());
+
+        // without a subcommand, the first argument is the first path
+        let (input, _) = aggregate("a.txt b.txt");
+        assert_eq!(input.paths, [Path::new("a.txt"), Path::new("b.txt")]);
+        match parse_str("validate") {
+            Ok(Command::Validate(input)) => {
+                assert_eq!(input.paths, [Path::new("measurements.txt")]);
+                assert_eq!(input.config.policy, Policy::Collect(COLLECT_LIMIT));
+            }
+            _ => panic!("validate"),
+        }
+        match parse_str("generate --rows 5") {
+            Ok(Command::Generate(generate)) => {
+                assert_eq!(generate.path, Path::new("measurements.txt"));
+                assert_eq!((generate.rows, generate.seed), (5, None));
+            }
+            _ => panic!("generate"),
+        }
+        assert!(matches!(parse_str("merge -h"), Ok(Command::Help)));
+        assert!(matches!(parse_str("-V"), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn inline_values() {
+        let (input, output) = aggregate("--format=json --threads=3 -o=out.json --window hour a.txt");
+        assert_eq!(output.format, Format::Json);
+        assert_eq!(output.path.as_deref(), Some(Path::new("out.json")));
+        assert_eq!(input.config.threads, 3);
+        assert_eq!(input.config.window, Some(Window::Hour));
+        assert_eq!(input.paths, [Path::new("a.txt")]);
+        assert_eq!(error("--threads"), "--threads needs a value");
+        assert_eq!(error("--threads=0"), "--threads must be a positive number");
+    }
+
+    // Each subcommand only takes its own options
+    #[test]
+    fn subcommand_options() {
+        assert!(aggregate("aggregate --variance --percentiles").0.config.variance);
+        assert_eq!(error("validate --variance"), "unexpected option '--variance'");
+        assert_eq!(error("validate --format json"), "unexpected option '--format'");
+        assert_eq!(error("merge --mmap a.brcp"), "unexpected option '--mmap'");
+        assert_eq!(error("generate --threads 2"), "unexpected option '--threads'");
+        assert_eq!(error("aggregate --rows 5"), "unexpected option '--rows'");
+        assert_eq!(error("--bogus"), "unexpected option '--bogus'");
+    }
+
+    #[test]
+    fn invalid_combinations() {
+        assert_eq!(error("--mmap a.txt b.txt"), "--mmap needs a single input file");
+        assert_eq!(error("--mmap -"), "--mmap needs a single input file");
+        assert_eq!(error("--resume"), "--resume needs a --checkpoint file");
+        assert_eq!(error("merge"), "merge needs at least one partial");
+        assert_eq!(error("generate a.txt b.txt"), "generate writes a single file");
+        assert!(aggregate("--checkpoint a.brck --resume a.txt").0.checkpoint.is_some_and(|c| c.resume));
+    }
+
+    // A line has to fit in a block along with its newline
+    #[test]
+    fn block_size() {
+        let err = "--block-size must be larger than --max-line-len";
+        assert_eq!(error("--block-size 4096"), err);
+        assert_eq!(error("--block-size 100 --max-line-len 100"), err);
+        assert_eq!(error("validate --max-line-len 60000"), err);
+        let (input, _) = aggregate("--block-size 100 --max-line-len 99");
+        assert_eq!((input.config.block_size, input.config.max_line_len), (100, 99));
+    }
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(aggregate("--delimiter ,").0.config.schema.delimiter, b',');
+        assert_eq!(aggregate("--delimiter=|").0.config.schema.delimiter, b'|');
+        assert_eq!(aggregate(r"--delimiter \t").0.config.schema.delimiter, b'\t');
+        for delimiter in ["", "ab", ".", "-", "+", "0", "7", "\r", "\n", "é"] {
+            let args = ["--delimiter".to_string(), delimiter.to_string()];
+            assert!(matches!(parse(args), Err(err) if err.starts_with("--delimiter must be")), "{delimiter:?}");
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(ParseError),
+    /// Reading or writing the checkpoint of `aggregate_resumable` failed.
+    Checkpoint(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "failed to read input: {err}"),
+            Error::Parse(err) => err.fmt(f),
+            Error::Checkpoint(err) => write!(f, "checkpoint: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::Checkpoint(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// A line that couldn't be parsed, along with where it starts in the input.
+///
+/// When aggregating a set of shard files or several inputs, `file` is the one the line is in and `offset`
+/// is relative to its start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: u64,
+    pub kind: ParseErrorKind,
+    pub file: Option<Arc<Path>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    MissingSeparator,
+    InvalidMeasurement,
+    InvalidTimestamp,
+    LineTooLong,
+    MissingColumn,
+    TooManyDecimals,
+    InvalidUtf8,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "invalid line at byte {} of {}: {}", self.offset, file.display(), self.kind),
+            None => write!(f, "invalid line at byte {}: {}", self.offset, self.kind),
+        }
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ParseErrorKind::MissingSeparator => "missing separator",
+            ParseErrorKind::InvalidMeasurement => "measurement isn't a valid number",
+            ParseErrorKind::InvalidTimestamp => "timestamp isn't unix seconds or an ISO-8601 UTC time",
+            ParseErrorKind::LineTooLong => "line is longer than the maximum line length",
+            ParseErrorKind::MissingColumn => "fewer columns than the schema",
+            ParseErrorKind::TooManyDecimals => "measurement has more decimals than the configured precision",
+            ParseErrorKind::InvalidUtf8 => "station name isn't valid UTF-8",
+        })
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// For partial and checkpoint files that can't be read back
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Summary of the lines that were skipped under `Policy::Skip` or `Policy::Collect`.
+///
+/// `first` holds the earliest offenders in input order, up to the `Policy::Collect` limit.
+#[derive(Debug, Default)]
+pub struct Rejected {
+    pub count: u64,
+    pub first: Vec<ParseError>,
+}
+
+impl Rejected {
+    pub(crate) fn record(&mut self, err: ParseError, limit: usize) {
+        self.count += 1;
+        if self.first.len() < limit {
+            self.first.push(err);
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: Rejected, limit: usize) {
+        self.count += other.count;
+        self.first.extend(other.first);
+        self.first.sort_unstable_by(|a, b| (&a.file, a.offset).cmp(&(&b.file, b.offset)));
+        self.first.truncate(limit);
+    }
+}
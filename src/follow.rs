@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::checkpoint::add_segment;
+use crate::{Config, Error, Results};
+
+// A file that's still being appended to is aggregated one poll at a time: every complete line that was
+// added since the last poll is aggregated as a segment into the running results. A last line that's still
+// being written is left for the next poll.
+
+pub(crate) fn follow_aggregate(
+    file: &File,
+    interval: Duration,
+    config: &Config,
+    mut on_snapshot: impl FnMut(&Results) -> ControlFlow<()>,
+) -> Result<(), Error> {
+    let mut offset = 0;
+    let mut results = Results::default();
+    loop {
+        let len = file.metadata()?.len();
+        if len < offset {
+            return Err(io::Error::other("file was truncated while following it").into());
+        }
+        let end = last_line_end(file, offset, len)?;
+        if end > offset {
+            add_segment(file, offset, end, &mut results, config)?;
+            offset = end;
+            if on_snapshot(&results).is_break() {
+                return Ok(());
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+// Just after the last newline in start..len, or start if there's no complete line there yet
+fn last_line_end(mut file: &File, start: u64, len: u64) -> io::Result<u64> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut end = len;
+    while end > start {
+        let from = end.saturating_sub(buf.len() as u64).max(start);
+        let window = &mut buf[..(end - from) as usize];
+        file.seek(SeekFrom::Start(from))?;
+        file.read_exact(window)?;
+        if let Some(nl) = memchr::memrchr(b'\n', window) {
+            return Ok(from + nl as u64 + 1);
+        }
+        end = from;
+    }
+    Ok(start)
+}
@@ -0,0 +1,31 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use rand::prelude::*;
+use rand_distr::Normal;
+
+use crate::cli::Generate;
+
+// the station list is shared with the standalone generator
+#[path = "generate/src/stations.rs"]
+mod stations;
+
+use stations::STATIONS;
+
+const TEMPERATURE_STD: f64 = 10.;
+
+pub(crate) fn generate(args: &Generate) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create_new(&args.path)?);
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+
+    for _ in 0..args.rows {
+        let &(name, mean) = STATIONS.choose(&mut rng).unwrap();
+        let measurement: f64 = Normal::new(mean, TEMPERATURE_STD).unwrap().sample(&mut rng);
+        // keep within the challenge's -99.9..=99.9 range
+        writeln!(out, "{name};{:.1}", measurement.clamp(-99.9, 99.9))?;
+    }
+    out.flush()
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+// Measurements are whole tenths, nearly always within -99.9..=99.9, so a bucket per possible value gives
+// exact percentiles for the price of 1999 counters per station. The first measurement outside of that
+// range (up to 999.9 is valid) switches the histogram over to only counting the values that actually occur.
+//
+// With `Schema::decimals`, measurements can be anything that fits in an i32, and with windows there's a
+// histogram per station and window with only a few measurements in each, so histograms are sparse from
+// the start in both cases.
+
+const MIN: i32 = -999;
+const MAX: i32 = 999;
+const BUCKETS: usize = (MAX - MIN + 1) as usize;
+
+#[derive(Clone)]
+pub(crate) enum Histogram {
+    Dense(Box<[u64]>),
+    Sparse(BTreeMap<i32, u64>),
+}
+
+impl Histogram {
+    pub(crate) fn new() -> Self {
+        Self::Dense(vec![0; BUCKETS].into_boxed_slice())
+    }
+
+    pub(crate) fn sparse() -> Self {
+        Self::Sparse(BTreeMap::new())
+    }
+
+    pub(crate) fn add(&mut self, measurement: i32) {
+        self.add_count(measurement, 1);
+    }
+
+    pub(crate) fn add_count(&mut self, measurement: i32, n: u64) {
+        match self {
+            Self::Dense(buckets) if (MIN..=MAX).contains(&measurement) => buckets[(measurement - MIN) as usize] += n,
+            Self::Dense(_) => {
+                let mut sparse = Self::Sparse(self.buckets().collect());
+                sparse.add_count(measurement, n);
+                *self = sparse;
+            }
+            Self::Sparse(counts) => *counts.entry(measurement).or_default() += n,
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        match (self, other) {
+            (Self::Dense(a), Self::Dense(b)) => a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a += b),
+            (this, other) => other.buckets().for_each(|(measurement, n)| this.add_count(measurement, n)),
+        }
+    }
+
+    pub(crate) fn is_sparse(&self) -> bool {
+        matches!(self, Self::Sparse(_))
+    }
+
+    // Nearest-rank percentile: the smallest measurement that at least p of all measurements are at or below.
+    // count is the total number of measurements, which the station already knows.
+    pub(crate) fn percentile(&self, p: f64, count: u64) -> i32 {
+        // nudged down so that 0.95 * 100 = 95.00000000000001 is rank 95, not 96
+        let rank = ((p * count as f64 * (1.0 - f64::EPSILON)).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        let mut last = MAX;
+        for (measurement, n) in self.buckets() {
+            seen += n;
+            if seen >= rank {
+                return measurement;
+            }
+            last = measurement;
+        }
+        last
+    }
+
+    // (scaled measurement, count) for every measurement that occurred, in ascending order
+    pub(crate) fn buckets(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        match self {
+            Self::Dense(buckets) => Box::new(
+                buckets
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &n)| n != 0)
+                    .map(|(i, &n)| (i as i32 + MIN, n)),
+            ),
+            Self::Sparse(counts) => Box::new(counts.iter().map(|(&measurement, &n)| (measurement, n))),
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::ParseError;
+
+/// Reads several inputs one after the other, as if they were concatenated.
+///
+/// An input that doesn't end with a newline gets one added, so that its last line doesn't run into
+/// the first line of the next input.
+pub struct Concat<R> {
+    readers: std::vec::IntoIter<(Arc<Path>, R)>,
+    current: Option<R>,
+    ends_with_newline: bool,
+    // bytes handed out so far
+    offset: u64,
+    origins: Origins,
+}
+
+impl<R: Read> Concat<R> {
+    pub fn new(readers: Vec<(Arc<Path>, R)>) -> Self {
+        let origins = Origins(Arc::new(Mutex::new(Vec::new())));
+        let mut readers = readers.into_iter();
+        let current = readers.next().map(|(path, reader)| {
+            origins.0.lock().unwrap().push((0, path));
+            reader
+        });
+        Self {
+            readers,
+            current,
+            ends_with_newline: true,
+            offset: 0,
+            origins,
+        }
+    }
+
+    /// Where each input starts in the concatenated stream, to find out which input an error is in later.
+    pub fn origins(&self) -> Origins {
+        self.origins.clone()
+    }
+}
+
+impl<R: Read> Read for Concat<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(reader) = &mut self.current {
+            let read = reader.read(buf)?;
+            if read > 0 {
+                self.ends_with_newline = buf[read - 1] == b'\n';
+                self.offset += read as u64;
+                return Ok(read);
+            }
+            let insert = !self.ends_with_newline;
+            self.current = self.readers.next().map(|(path, reader)| {
+                // the added newline still belongs to the previous input
+                let start = self.offset + u64::from(insert);
+                self.origins.0.lock().unwrap().push((start, path));
+                reader
+            });
+            if self.current.is_some() && insert {
+                self.ends_with_newline = true;
+                self.offset += 1;
+                buf[0] = b'\n';
+                return Ok(1);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// The inputs of a `Concat`, and where each of them starts in the concatenated stream.
+#[derive(Clone)]
+pub struct Origins(Arc<Mutex<Starts>>);
+
+type Starts = Vec<(u64, Arc<Path>)>;
+
+impl Origins {
+    /// Turn an offset in the concatenated stream into the input it's in and the offset within that input.
+    ///
+    /// With a single input, offsets already are relative to it, and errors are left as they are.
+    pub fn locate(&self, err: &mut ParseError) {
+        let origins = self.0.lock().unwrap();
+        if origins.len() < 2 {
+            return;
+        }
+        let i = origins.partition_point(|(start, _)| *start <= err.offset).saturating_sub(1);
+        let (start, path) = &origins[i];
+        err.offset -= start;
+        err.file = Some(path.clone());
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+// size of the chunks handed from the decompression thread to the reader
+const CHUNK_SIZE: usize = 1 << 18;
+
+/// Transparently decompress gzip or zstd input, detected by its magic bytes.
+///
+/// Compressed input is decompressed on its own thread, so that the reader thread only has to copy
+/// the decompressed data into blocks. Anything else is passed through as-is, straight from `reader`
+/// after the few bytes that were peeked at.
+pub fn decompress<R: Read + Send + 'static>(mut reader: R) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    reader.by_ref().take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let compressed = is_magic(&magic);
+    let reader = io::Cursor::new(magic).chain(reader);
+    match compressed {
+        Some(Compression::Gzip) => Ok(Box::new(Pipe::spawn(MultiGzDecoder::new(buffered(reader))))),
+        Some(Compression::Zstd) => Ok(Box::new(Pipe::spawn(zstd::Decoder::with_buffer(buffered(reader))?))),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+/// Whether the file at `path` is gzip or zstd compressed, which only `decompress` handles.
+pub fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    File::open(path)?.take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    Ok(is_magic(&magic).is_some())
+}
+
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+fn is_magic(start: &[u8]) -> Option<Compression> {
+    if start.starts_with(GZIP_MAGIC) {
+        Some(Compression::Gzip)
+    } else if start.starts_with(ZSTD_MAGIC) {
+        Some(Compression::Zstd)
+    } else {
+        None
+    }
+}
+
+// the decoders read in small pieces
+fn buffered<R: Read>(reader: R) -> BufReader<R> {
+    BufReader::with_capacity(CHUNK_SIZE, reader)
+}
+
+// Reading end of a decoder running on another thread
+struct Pipe {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Pipe {
+    fn spawn<R: Read + Send + 'static>(mut decoder: R) -> Self {
+        let (tx, rx) = mpsc::sync_channel(4);
+        std::thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let read = match decoder.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        break;
+                    }
+                };
+                chunk.truncate(read);
+                // the reader hung up, so there's no point in decompressing the rest
+                if tx.send(Ok(chunk)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // the decoder is done
+                Err(_) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.pos);
+        buf[..read].copy_from_slice(&self.chunk[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/// Expand a shard location into the files it refers to, sorted by name.
+///
+/// A directory stands for all files directly inside of it, and a file name with `*` or `?` wildcards
+/// for the matching files in its directory. Anything else is taken as a single file. A directory or
+/// pattern without any files in it is an error, rather than silently aggregating nothing.
+pub fn shard_paths(location: &Path) -> io::Result<Vec<PathBuf>> {
+    let (dir, pattern) = if location.is_dir() {
+        (location, None)
+    } else {
+        match location.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains(['*', '?']) => {
+                let dir = location.parent().filter(|dir| !dir.as_os_str().is_empty());
+                (dir.unwrap_or(Path::new(".")), Some(name))
+            }
+            _ => return Ok(vec![location.to_path_buf()]),
+        }
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let matches = pattern.is_none_or(|pattern| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| wildcard_match(pattern.as_bytes(), name.as_bytes()))
+        });
+        if matches && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no files match"));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// `*` matches any run of bytes, `?` any single byte
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        [b'*', rest @ ..] => (0..=name.len()).any(|skip| wildcard_match(rest, &name[skip..])),
+        [b'?', rest @ ..] => !name.is_empty() && wildcard_match(rest, &name[1..]),
+        [c, rest @ ..] => name.first() == Some(c) && wildcard_match(rest, &name[1..]),
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::worker::{Aggregator, Buffers, Parsers, merge, read_parse, read_worker};
+
+mod checkpoint;
+mod error;
+mod follow;
+mod histogram;
+pub mod input;
+mod mmap;
+pub mod output;
+pub mod partial;
+mod ref_hash_map;
+mod shards;
+mod time;
+mod worker;
+
+pub use crate::error::{Error, ParseError, ParseErrorKind, Rejected};
+pub use crate::worker::Station;
+
+/// Stations with their aggregates, sorted by name.
+pub type Stations = Vec<(Box<str>, Station)>;
+
+/// Per-station aggregates sorted by station name, plus the lines that were skipped.
+#[derive(Default)]
+pub struct Results {
+    pub stations: Stations,
+    /// With `Config::window`, the stations of every window by its start in unix seconds, in ascending order.
+    /// `stations` then holds the totals over all windows.
+    pub windows: Vec<(i64, Stations)>,
+    pub rejected: Rejected,
+}
+
+/// What to do with lines that can't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Stop at the first invalid line, and return it as an error.
+    FailFast,
+    /// Skip invalid lines, only counting them.
+    Skip,
+    /// Skip invalid lines, counting them and keeping the first `n` of them.
+    Collect(usize),
+}
+
+/// Time windows to aggregate timestamped input in, aligned to UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Hour,
+    Day,
+}
+
+impl Window {
+    pub fn seconds(self) -> i64 {
+        match self {
+            Window::Hour => 3_600,
+            Window::Day => 86_400,
+        }
+    }
+}
+
+/// How input lines are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schema {
+    /// The byte between columns.
+    pub delimiter: u8,
+    /// Which columns hold what, other columns are ignored. Without them, a line is the station and the measurement
+    /// (with `Config::window`, the station, the timestamp and the measurement), and the station can contain the
+    /// delimiter.
+    pub columns: Option<Columns>,
+    /// Accept integers, a leading `+` and up to this many decimals, instead of exactly one decimal. Measurements
+    /// are kept as integers scaled by `10^decimals`, so they have to fit in an `i32` once scaled.
+    pub decimals: Option<u8>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self {
+            delimiter: b';',
+            columns: None,
+            decimals: None,
+        }
+    }
+}
+
+/// Zero-based column indexes. Lines with too few columns are invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns {
+    pub station: usize,
+    pub measurement: usize,
+    /// Needed with `Config::window`.
+    pub timestamp: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub policy: Policy,
+    /// Total number of threads to use, including the reader thread. Defaults to all available cores.
+    pub threads: usize,
+    /// Size in bytes of the blocks the reader hands to the parsers.
+    pub block_size: usize,
+    /// Number of blocks in flight per parser thread.
+    pub blocks_per_thread: usize,
+    /// Longest valid line in bytes, not counting the `\n` (but a `\r` before it). Longer lines are rejected as
+    /// `ParseErrorKind::LineTooLong`. Has to be smaller than `block_size`.
+    pub max_line_len: usize,
+    /// Also track the variance and standard deviation per station, at a small cost per line.
+    pub variance: bool,
+    /// Also track the median, 95th and 99th percentile per station, with a histogram of all measurements.
+    pub percentiles: bool,
+    /// Lines are `station;timestamp;temperature` instead, aggregated per station and window. Timestamps are
+    /// unix seconds or ISO-8601 UTC times (`2024-06-01T13:45:00Z`).
+    pub window: Option<Window>,
+    pub schema: Schema,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            policy: Policy::FailFast,
+            threads: available_threads(),
+            block_size: 50_000,
+            blocks_per_thread: 3,
+            max_line_len: 4096,
+            variance: false,
+            percentiles: false,
+            window: None,
+            schema: Schema::default(),
+        }
+    }
+}
+
+/// Run the reader / parser pipeline over `reader`.
+///
+/// The reader runs on the calling thread, the parsers each get their own thread.
+/// With only a single thread, reading and parsing are fused on the calling thread instead.
+pub fn aggregate<R: Read>(reader: R, config: &Config) -> Result<Results, Error> {
+    let threads = config.threads;
+    if threads < 2 {
+        let mut agg = Aggregator::new(config);
+        read_parse(&mut agg, reader, config)?;
+        return Ok(merge([Ok(agg)], config.policy)?);
+    }
+    let parse_threads = threads - 1;
+
+    let buffers: Buffers = Buffers::new(parse_threads, config);
+    let parsers = Parsers::start(parse_threads, &buffers, config);
+
+    // even if reading fails, the parsers are joined first so that they don't outlive the reader
+    let read = read_worker(buffers, reader, config);
+    let results = parsers.join();
+    read?;
+    Ok(results?)
+}
+
+/// Aggregate a file by memory-mapping it, instead of copying it into buffers through a reader thread.
+///
+/// Every thread is a parser, each working on its own newline-aligned range of the file.
+pub fn aggregate_mmap(file: &File, config: &Config) -> Result<Results, Error> {
+    mmap::mmap_aggregate(file, config)
+}
+
+/// Aggregate a set of shard files together, giving each thread whole files to read and parse.
+///
+/// This scales with the number of files rather than being limited by a single reader thread.
+/// Compressed shards are decompressed like with `input::decompress`.
+pub fn aggregate_files(paths: Vec<PathBuf>, config: &Config) -> Result<Results, Error> {
+    shards::shards_aggregate(paths, config)
+}
+
+/// Aggregate a file in newline-aligned segments of about `every` bytes, saving the state so far to `checkpoint`
+/// after each one.
+///
+/// With `resume`, an existing checkpoint is picked up where it left off, giving the same results as an
+/// uninterrupted run. The checkpoint is removed once the whole file is aggregated.
+pub fn aggregate_resumable(
+    file: &File,
+    checkpoint: &Path,
+    every: u64,
+    resume: bool,
+    config: &Config,
+) -> Result<Results, Error> {
+    checkpoint::checkpoint_aggregate(file, checkpoint, every, resume, config)
+}
+
+/// Aggregate a file that is still being appended to, polling it for new lines every `interval`.
+///
+/// `on_snapshot` gets the results so far each time complete lines were added, and can return
+/// `ControlFlow::Break` to stop following. A line is only aggregated once its newline is there, and
+/// no line is ever aggregated twice.
+pub fn aggregate_follow(
+    file: &File,
+    interval: Duration,
+    config: &Config,
+    on_snapshot: impl FnMut(&Results) -> ControlFlow<()>,
+) -> Result<(), Error> {
+    follow::follow_aggregate(file, interval, config, on_snapshot)
+}
+
+fn available_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.into())
+}
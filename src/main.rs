@@ -1,32 +1,211 @@
 use std::fs::File;
-use crate::worker::{Buffers, Parsers, read_worker, Station};
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
 
-mod ref_hash_map;
-mod worker;
+use brc::input::{Concat, decompress, shard_paths};
+use brc::output::{write_histograms, write_results};
+use brc::partial::{merge, read_partial, write_partial};
+use brc::{
+    Error, Rejected, Results, aggregate, aggregate_files, aggregate_follow, aggregate_mmap, aggregate_resumable,
+};
 
+use crate::cli::{Command, Input, Output, USAGE};
 
-fn main() {
-    let path = std::env::args().nth(1).unwrap_or_else(|| "measurements.txt".into());
-    let file = File::open(path).expect("File is missing, please a correct path as the first argument");
-    let threads: usize = std::thread::available_parallelism().unwrap().into();
-    assert!(threads > 1, "This program expects to have at least two cores, and doesn't work single-threaded");
-    let parse_threads = threads - 1;
+mod cli;
+mod generate;
 
-    let buffers: Buffers = Buffers::new(parse_threads);
-    let parsers = Parsers::start(parse_threads, &buffers);
+fn main() -> ExitCode {
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {err}\n\nFor more information, try '--help'.");
+            return ExitCode::from(2);
+        }
+    };
 
-    read_worker(buffers, file);
+    match command {
+        Command::Help => {
+            print!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        Command::Version => {
+            println!("brc {}", env!("CARGO_PKG_VERSION"));
+            ExitCode::SUCCESS
+        }
+        Command::Aggregate(input, output) if input.follow.is_some() => follow(&input, &output),
+        Command::Aggregate(input, output) => match run(&input) {
+            Ok(res) => emit(&res, &output),
+            Err(err) => fail(err),
+        },
+        Command::Merge(paths, output) => {
+            let parts = paths
+                .iter()
+                .map(|path| {
+                    let file = open(path)?;
+                    read_partial(io::BufReader::new(file)).map_err(|err| format!("failed to read {}: {err}", path.display()))
+                })
+                .collect::<Result<Vec<_>, _>>();
+            match parts {
+                Ok(parts) => match merge(parts) {
+                    Ok(res) => emit(&res, &output),
+                    Err(err) => fail(format!("failed to merge: {err}")),
+                },
+                Err(err) => fail(err),
+            }
+        }
+        Command::Validate(input) => match run(&input) {
+            Ok(res) => {
+                let valid: u64 = res.stations.iter().map(|(_, s)| s.count()).sum();
+                println!("{valid} valid lines, {} invalid lines", res.rejected.count);
+                show_rejected(&res.rejected);
+                if res.rejected.count == 0 {
+                    ExitCode::SUCCESS
+                } else {
+                    ExitCode::FAILURE
+                }
+            }
+            Err(err) => fail(err),
+        },
+        Command::Generate(args) => match generate::generate(&args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => fail(format!("failed to write {}: {err}", args.path.display())),
+        },
+    }
+}
 
-    show_results(parsers.join());
+fn run(input: &Input) -> Result<Results, String> {
+    if input.shards {
+        let mut files = Vec::new();
+        for location in &input.paths {
+            let paths = shard_paths(location).map_err(|err| format!("failed to list {}: {err}", location.display()))?;
+            files.extend(paths);
+        }
+        return aggregate_files(files, &input.config).map_err(|err| err.to_string());
+    }
+    if let Some(checkpoint) = &input.checkpoint {
+        let path = &input.paths[0];
+        let file = open(path)?;
+        return aggregate_resumable(&file, &checkpoint.path, checkpoint.every, checkpoint.resume, &input.config)
+            .map_err(|err| format!("{}: {err}", path.display()));
+    }
+    if input.mmap {
+        let path = &input.paths[0];
+        let file = open(path)?;
+        return aggregate_mmap(&file, &input.config).map_err(|err| format!("{}: {err}", path.display()));
+    }
+    let readers = input
+        .paths
+        .iter()
+        .map(|path| {
+            let reader: Box<dyn Read + Send> = if path == Path::new("-") {
+                Box::new(io::stdin())
+            } else {
+                Box::new(open(path)?)
+            };
+            let reader = decompress(reader).map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+            let name = if path == Path::new("-") { Path::new("<stdin>") } else { path };
+            Ok((Arc::from(name), reader))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let concat = Concat::new(readers);
+    let origins = concat.origins();
+    match aggregate(concat, &input.config) {
+        Ok(mut res) => {
+            res.rejected.first.iter_mut().for_each(|err| origins.locate(err));
+            Ok(res)
+        }
+        Err(Error::Parse(mut err)) => {
+            origins.locate(&mut err);
+            Err(err.to_string())
+        }
+        Err(err) => Err(err.to_string()),
+    }
 }
 
-fn show_results(res: Vec<(Box<str>, Station)>) {
-    print!("{{");
-    for (i, (name, station)) in res.into_iter().enumerate() {
-        if i != 0 {
-            print!(", ");
+fn open(path: &Path) -> Result<File, String> {
+    File::open(path).map_err(|err| format!("failed to open {}: {err}", path.display()))
+}
+
+// Outputs a snapshot every time new lines were appended, until writing one fails
+fn follow(input: &Input, output: &Output) -> ExitCode {
+    let path = &input.paths[0];
+    let file = match open(path) {
+        Ok(file) => file,
+        Err(err) => return fail(err),
+    };
+    let mut written = Ok(());
+    let followed = aggregate_follow(&file, input.follow.unwrap(), &input.config, |res| {
+        written = write(res, output);
+        if written.is_err() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
         }
-        print!("{name}={station}");
+    });
+    match followed.map_err(|err| format!("{}: {err}", path.display())).and(written) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => fail(err),
     }
-    println!("}}");
-}
\ No newline at end of file
+}
+
+fn emit(res: &Results, output: &Output) -> ExitCode {
+    if let Err(err) = write(res, output) {
+        return fail(err);
+    }
+    show_rejected(&res.rejected);
+    ExitCode::SUCCESS
+}
+
+fn write(res: &Results, output: &Output) -> Result<(), String> {
+    if let Some(path) = &output.partial {
+        return write_file(path, |w| write_partial(w, res));
+    }
+    let render = |w: &mut dyn Write| {
+        if output.histogram {
+            write_histograms(w, res, output.format)
+        } else {
+            write_results(w, res, output.format)
+        }
+    };
+    match &output.path {
+        Some(path) => write_file(path, render),
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            render(&mut out)
+                .and_then(|()| out.flush())
+                .map_err(|err| format!("failed to write results: {err}"))
+        }
+    }
+}
+
+// Written next to path and renamed over it, so that it's never seen half-written, even while following
+fn write_file(path: &Path, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    File::create(&tmp)
+        .and_then(|file| {
+            let mut w = BufWriter::new(file);
+            write(&mut w)?;
+            w.flush()
+        })
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|err| format!("failed to write {}: {err}", path.display()))
+}
+
+fn fail(err: String) -> ExitCode {
+    eprintln!("error: {err}");
+    ExitCode::FAILURE
+}
+
+fn show_rejected(rejected: &Rejected) {
+    if rejected.count == 0 {
+        return;
+    }
+    eprintln!("{} invalid lines were skipped", rejected.count);
+    for err in &rejected.first {
+        eprintln!("  {err}");
+    }
+}
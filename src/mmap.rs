@@ -0,0 +1,53 @@
+use std::fs::File;
+
+use memmap2::Mmap;
+
+use crate::worker::{Aggregator, merge};
+use crate::{Config, Error, Results};
+
+// Alternative to the reader thread + buffers: map the whole file, and give every parser thread its own
+// newline-aligned range of it. There's no copying at all, the OS pages the file in as the parsers go.
+
+pub(crate) fn mmap_aggregate(file: &File, config: &Config) -> Result<Results, Error> {
+    if file.metadata()?.len() == 0 {
+        return Ok(merge([], config.policy)?);
+    }
+    let map = unsafe { Mmap::map(file)? };
+    let data: &[u8] = &map;
+
+    let aggs = std::thread::scope(|s| {
+        chunks(data, config.threads.max(1))
+            .map(|(offset, chunk)| {
+                s.spawn(move || {
+                    let mut agg = Aggregator::new(config);
+                    agg.parse(chunk, offset as u64).map(|()| agg)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    Ok(merge(aggs, config.policy)?)
+}
+
+// Split data into (at most) n ranges, each ending just after a newline (except possibly the last),
+// along with the offset each of them starts at
+fn chunks(data: &[u8], n: usize) -> impl Iterator<Item = (usize, &[u8])> {
+    let target = data.len().div_ceil(n);
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let rest = &data[offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let end = if rest.len() <= target {
+            rest.len()
+        } else {
+            memchr::memchr(b'\n', &rest[target..]).map_or(rest.len(), |nl| target + nl + 1)
+        };
+        let start = offset;
+        offset += end;
+        Some((start, &rest[..end]))
+    })
+}
@@ -0,0 +1,285 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::{Results, Station};
+use crate::time::Timestamp;
+use crate::worker::{Fixed, PERCENTILES};
+
+/// How aggregation results are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The challenge's `{name=min/mean/max, ...}` line.
+    Text,
+    /// An object keyed by station name, with numeric `min`, `mean`, `max`, `count` and `sum` fields.
+    Json,
+    /// Comma-separated, with a header row.
+    Csv,
+    /// Tab-separated, with a header row.
+    Tsv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("unknown output format '{s}', expected text, json, csv or tsv")),
+        }
+    }
+}
+
+/// Write the results, or with `Config::window` the results of every window, labelled with its ISO-8601 start.
+pub fn write_results<W: Write>(mut w: W, res: &Results, format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => write_text(&mut w, res),
+        Format::Json => write_json(&mut w, res),
+        Format::Csv => write_delimited(&mut w, res, ','),
+        Format::Tsv => write_delimited(&mut w, res, '\t'),
+    }
+}
+
+/// Dump every station's histogram (measurement -> count, for every measurement that occurred).
+///
+/// Stations without a histogram (`Config::percentiles` wasn't set) are written as empty. With `Config::window`,
+/// these are the histograms over all windows.
+pub fn write_histograms<W: Write>(mut w: W, res: &Results, format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => write_histograms_text(&mut w, res),
+        Format::Json => write_histograms_json(&mut w, res),
+        Format::Csv => write_histograms_delimited(&mut w, res, ','),
+        Format::Tsv => write_histograms_delimited(&mut w, res, '\t'),
+    }
+}
+
+// With windows, one line per window, prefixed by its start
+fn write_text<W: Write>(w: &mut W, res: &Results) -> io::Result<()> {
+    if res.windows.is_empty() {
+        return write_text_stations(w, &res.stations);
+    }
+    for (start, stations) in &res.windows {
+        write!(w, "{} ", Timestamp(*start))?;
+        write_text_stations(w, stations)?;
+    }
+    Ok(())
+}
+
+fn write_text_stations<W: Write>(w: &mut W, stations: &[(Box<str>, Station)]) -> io::Result<()> {
+    write!(w, "{{")?;
+    for (i, (name, station)) in stations.iter().enumerate() {
+        if i != 0 {
+            write!(w, ", ")?;
+        }
+        write!(w, "{name}={station}")?;
+    }
+    writeln!(w, "}}")
+}
+
+// With windows, an object keyed by the start of each window, holding that window's stations
+fn write_json<W: Write>(w: &mut W, res: &Results) -> io::Result<()> {
+    if res.windows.is_empty() {
+        write_json_stations(w, &res.stations, "")?;
+        return writeln!(w);
+    }
+    write!(w, "{{")?;
+    for (i, (start, stations)) in res.windows.iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\n  \"{}\": ", Timestamp(*start))?;
+        write_json_stations(w, stations, "  ")?;
+    }
+    writeln!(w, "\n}}")
+}
+
+// One station per line, in the same (sorted) order as the results
+fn write_json_stations<W: Write>(w: &mut W, stations: &[(Box<str>, Station)], indent: &str) -> io::Result<()> {
+    write!(w, "{{")?;
+    for (i, (name, station)) in stations.iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\n{indent}  ")?;
+        write_json_string(w, name)?;
+        write!(
+            w,
+            ": {{\"min\": {}, \"mean\": {}, \"max\": {}, \"count\": {}, \"sum\": {}",
+            station.fixed(station.min as i64),
+            station.fixed(station.mean_scaled()),
+            station.fixed(station.max as i64),
+            station.count,
+            station.fixed(station.sum),
+        )?;
+        if let (Some(variance), Some(std_dev)) = (station.variance(), station.std_dev()) {
+            write!(w, ", \"variance\": {variance}, \"stddev\": {std_dev}")?;
+        }
+        for (label, p) in PERCENTILES {
+            if let Some(scaled) = station.percentile_scaled(p) {
+                write!(w, ", \"{label}\": {}", station.fixed(scaled as i64))?;
+            }
+        }
+        write!(w, "}}")?;
+    }
+    if !stations.is_empty() {
+        write!(w, "\n{indent}")?;
+    }
+    write!(w, "}}")
+}
+
+fn write_json_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write!(w, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(w, "\\\"")?,
+            '\\' => write!(w, "\\\\")?,
+            '\n' => write!(w, "\\n")?,
+            '\r' => write!(w, "\\r")?,
+            '\t' => write!(w, "\\t")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{c}")?,
+        }
+    }
+    write!(w, "\"")
+}
+
+// The variance and percentile columns are only there if they were tracked, and the window column if there
+// are windows. Stations that didn't track what another station did have empty fields there.
+fn write_delimited<W: Write>(w: &mut W, res: &Results, delimiter: char) -> io::Result<()> {
+    let variance = res.stations.iter().any(|(_, station)| station.sum_sq.is_some());
+    let percentiles = res.stations.iter().any(|(_, station)| station.histogram.is_some());
+    if !res.windows.is_empty() {
+        write!(w, "window{delimiter}")?;
+    }
+    write!(w, "station{delimiter}min{delimiter}mean{delimiter}max{delimiter}count{delimiter}sum")?;
+    if variance {
+        write!(w, "{delimiter}variance{delimiter}stddev")?;
+    }
+    if percentiles {
+        for (label, _) in PERCENTILES {
+            write!(w, "{delimiter}{label}")?;
+        }
+    }
+    writeln!(w)?;
+    if res.windows.is_empty() {
+        for (name, station) in res.stations.iter() {
+            write_row(w, name, station, variance, percentiles, delimiter)?;
+        }
+    }
+    for (start, stations) in &res.windows {
+        for (name, station) in stations {
+            write!(w, "{}{delimiter}", Timestamp(*start))?;
+            write_row(w, name, station, variance, percentiles, delimiter)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_row<W: Write>(
+    w: &mut W,
+    name: &str,
+    station: &Station,
+    variance: bool,
+    percentiles: bool,
+    delimiter: char,
+) -> io::Result<()> {
+    write_field(w, name, delimiter)?;
+    write!(
+        w,
+        "{delimiter}{}{delimiter}{}{delimiter}{}{delimiter}{}{delimiter}{}",
+        station.fixed(station.min as i64),
+        station.fixed(station.mean_scaled()),
+        station.fixed(station.max as i64),
+        station.count,
+        station.fixed(station.sum),
+    )?;
+    if variance {
+        match (station.variance(), station.std_dev()) {
+            (Some(variance), Some(std_dev)) => write!(w, "{delimiter}{variance}{delimiter}{std_dev}")?,
+            _ => write!(w, "{delimiter}{delimiter}")?,
+        }
+    }
+    if percentiles {
+        for (_, p) in PERCENTILES {
+            match station.percentile_scaled(p) {
+                Some(scaled) => write!(w, "{delimiter}{}", station.fixed(scaled as i64))?,
+                None => write!(w, "{delimiter}")?,
+            }
+        }
+    }
+    writeln!(w)
+}
+
+// RFC 4180 style: quote the field if needed, doubling any quotes inside it
+fn write_field<W: Write>(w: &mut W, s: &str, delimiter: char) -> io::Result<()> {
+    if s.contains([delimiter, '"', '\n', '\r']) {
+        write!(w, "\"{}\"", s.replace('"', "\"\""))
+    } else {
+        write!(w, "{s}")
+    }
+}
+
+fn buckets(station: &Station) -> impl Iterator<Item = (Fixed, u64)> + '_ {
+    station
+        .histogram
+        .iter()
+        .flat_map(|histogram| histogram.buckets())
+        .map(|(scaled, n)| (station.fixed(scaled as i64), n))
+}
+
+// {name={measurement=count, ...}, ...}, like the text results
+fn write_histograms_text<W: Write>(w: &mut W, res: &Results) -> io::Result<()> {
+    write!(w, "{{")?;
+    for (i, (name, station)) in res.stations.iter().enumerate() {
+        if i != 0 {
+            write!(w, ", ")?;
+        }
+        write!(w, "{name}={{")?;
+        for (j, (measurement, n)) in buckets(station).enumerate() {
+            if j != 0 {
+                write!(w, ", ")?;
+            }
+            write!(w, "{measurement}={n}")?;
+        }
+        write!(w, "}}")?;
+    }
+    writeln!(w, "}}")
+}
+
+// JSON keys have to be strings, so the measurements are quoted
+fn write_histograms_json<W: Write>(w: &mut W, res: &Results) -> io::Result<()> {
+    write!(w, "{{")?;
+    for (i, (name, station)) in res.stations.iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\n  ")?;
+        write_json_string(w, name)?;
+        write!(w, ": {{")?;
+        for (j, (measurement, n)) in buckets(station).enumerate() {
+            if j != 0 {
+                write!(w, ", ")?;
+            }
+            write!(w, "\"{measurement}\": {n}")?;
+        }
+        write!(w, "}}")?;
+    }
+    if !res.stations.is_empty() {
+        writeln!(w)?;
+    }
+    writeln!(w, "}}")
+}
+
+// One row per (station, measurement)
+fn write_histograms_delimited<W: Write>(w: &mut W, res: &Results, delimiter: char) -> io::Result<()> {
+    writeln!(w, "station{delimiter}measurement{delimiter}count")?;
+    for (name, station) in res.stations.iter() {
+        for (measurement, n) in buckets(station) {
+            write_field(w, name, delimiter)?;
+            writeln!(w, "{delimiter}{measurement}{delimiter}{n}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read, Write};
+
+use crate::error::invalid;
+use crate::histogram::Histogram;
+use crate::worker::Merged;
+use crate::{Rejected, Results, Station};
+
+// A partial is the raw, pre-formatting aggregation state: the per-station integers (in tenths), so that
+// merging partials from several hosts gives exactly the same results as a single run would have.
+//
+// Layout, all little-endian:
+//   "BRCP" version:u8 rejected:u64 stations:u64, then per station:
+//     name_len:u32 name min:i32 max:i32 sum:i64 count:u64 flags:u8 decimals:u8
+//     flags & 1: sum_sq:u128
+//     flags & 2: buckets:u32, then per bucket measurement:i32 count:u64
+//     flags & 4: the histogram is sparse (see histogram.rs)
+//   windows:u64, then per window start:i64 stations:u64 and its stations as above
+
+const MAGIC: &[u8; 4] = b"BRCP";
+const VERSION: u8 = 1;
+const HAS_SUM_SQ: u8 = 1;
+const HAS_HISTOGRAM: u8 = 2;
+const SPARSE: u8 = 4;
+
+/// Write results as a partial, which can be merged with others later with `read_partial` and `merge`.
+///
+/// Only the number of rejected lines is kept, not where they were.
+pub fn write_partial<W: Write>(mut w: W, res: &Results) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    w.write_all(&res.rejected.count.to_le_bytes())?;
+    write_stations(&mut w, &res.stations)?;
+    w.write_all(&(res.windows.len() as u64).to_le_bytes())?;
+    for (start, stations) in &res.windows {
+        w.write_all(&start.to_le_bytes())?;
+        write_stations(&mut w, stations)?;
+    }
+    w.flush()
+}
+
+fn write_stations<W: Write>(w: &mut W, stations: &[(Box<str>, Station)]) -> io::Result<()> {
+    w.write_all(&(stations.len() as u64).to_le_bytes())?;
+    for (name, station) in stations {
+        w.write_all(&(name.len() as u32).to_le_bytes())?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&station.min.to_le_bytes())?;
+        w.write_all(&station.max.to_le_bytes())?;
+        w.write_all(&station.sum.to_le_bytes())?;
+        w.write_all(&station.count.to_le_bytes())?;
+        let flags = station.sum_sq.map_or(0, |_| HAS_SUM_SQ)
+            | station.histogram.as_ref().map_or(0, |histogram| {
+                if histogram.is_sparse() { HAS_HISTOGRAM | SPARSE } else { HAS_HISTOGRAM }
+            });
+        w.write_all(&[flags, station.decimals])?;
+        if let Some(sum_sq) = station.sum_sq {
+            w.write_all(&sum_sq.to_le_bytes())?;
+        }
+        if let Some(histogram) = &station.histogram {
+            w.write_all(&(histogram.buckets().count() as u32).to_le_bytes())?;
+            for (measurement, n) in histogram.buckets() {
+                w.write_all(&measurement.to_le_bytes())?;
+                w.write_all(&n.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read_partial<R: Read>(mut r: R) -> io::Result<Results> {
+    if &read_array::<4>(&mut r)? != MAGIC {
+        return Err(invalid("not a partial aggregate file"));
+    }
+    let [version] = read_array(&mut r)?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported partial version {version}")));
+    }
+    let rejected = u64::from_le_bytes(read_array(&mut r)?);
+    let stations = read_stations(&mut r)?;
+    let mut windows = Vec::new();
+    for _ in 0..u64::from_le_bytes(read_array(&mut r)?) {
+        let start = i64::from_le_bytes(read_array(&mut r)?);
+        windows.push((start, read_stations(&mut r)?));
+    }
+    Ok(Results {
+        stations,
+        windows,
+        rejected: Rejected {
+            count: rejected,
+            first: Vec::new(),
+        },
+    })
+}
+
+fn read_stations<R: Read>(r: &mut R) -> io::Result<Vec<(Box<str>, Station)>> {
+    let mut stations = Vec::new();
+    for _ in 0..u64::from_le_bytes(read_array(r)?) {
+        let len = u32::from_le_bytes(read_array(r)?) as usize;
+        let mut name = vec![0; len];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("station name isn't UTF-8"))?;
+        let min = i32::from_le_bytes(read_array(r)?);
+        let max = i32::from_le_bytes(read_array(r)?);
+        let sum = i64::from_le_bytes(read_array(r)?);
+        let count = u64::from_le_bytes(read_array(r)?);
+        let [flags, decimals] = read_array(r)?;
+        let sum_sq = match flags & HAS_SUM_SQ {
+            0 => None,
+            _ => Some(u128::from_le_bytes(read_array(r)?)),
+        };
+        let histogram = match flags & HAS_HISTOGRAM {
+            0 => None,
+            _ => {
+                let mut histogram = match flags & SPARSE {
+                    0 => Histogram::new(),
+                    _ => Histogram::sparse(),
+                };
+                for _ in 0..u32::from_le_bytes(read_array(r)?) {
+                    let measurement = i32::from_le_bytes(read_array(r)?);
+                    let n = u64::from_le_bytes(read_array(r)?);
+                    histogram.add_count(measurement, n);
+                }
+                Some(histogram)
+            }
+        };
+        let station = Station {
+            min,
+            max,
+            sum,
+            count,
+            sum_sq,
+            histogram,
+            decimals,
+        };
+        stations.push((name.into_boxed_str(), station));
+    }
+    Ok(stations)
+}
+
+/// Combine several results (typically read from partials) into one, as if they were a single run.
+///
+/// Fails if the results were aggregated with different `Schema::decimals`, as their sums and extremes
+/// are scaled differently.
+pub fn merge(parts: impl IntoIterator<Item = Results>) -> io::Result<Results> {
+    let mut rejected = Rejected::default();
+    let mut merged = Merged::default();
+    let mut decimals = None;
+    for part in parts {
+        let stations = part.stations.iter().chain(part.windows.iter().flat_map(|(_, stations)| stations));
+        for (_, station) in stations {
+            if *decimals.get_or_insert(station.decimals) != station.decimals {
+                return Err(invalid("partials were made with different --decimals"));
+            }
+        }
+        rejected.count += part.rejected.count;
+        rejected.first.extend(part.rejected.first);
+        for (name, station) in part.stations {
+            merged.add(name, None, station);
+        }
+        for (start, stations) in part.windows {
+            for (name, station) in stations {
+                merged.add(name, Some(start), station);
+            }
+        }
+    }
+    Ok(merged.finish(rejected))
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
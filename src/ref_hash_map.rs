@@ -65,7 +65,7 @@ impl<K, V> RefHashMap<K, V>
         Self(HashMap::with_capacity(capacity))
     }
 
-    pub(crate) fn entry_ref<'rf, 'map, R: 'rf, T>(
+    pub(crate) fn entry_ref<'rf, 'map, R, T>(
         &'map mut self,
         reference: R,
     ) -> Entry<'rf, K, V>
@@ -77,7 +77,7 @@ impl<K, V> RefHashMap<K, V>
         //      so the HashMap never holds reference types )
         //  - drop the fake K (we wrap it in a ManuallyDrop when we pass it to RefKey)
             'map: 'rf,
-            R: AsRef<[T]>,
+            R: 'rf + AsRef<[T]>,
             K: From<Vec<T>>,
     {
         let slice = reference.as_ref();
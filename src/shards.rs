@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::input::decompress;
+use crate::worker::{Aggregator, merge, read_parse};
+use crate::{Config, Error, Results};
+
+// For many (small) files, a single reader thread is the bottleneck: instead, every thread takes whole
+// files from a shared queue, and reads and parses them itself into its own map.
+
+pub(crate) fn shards_aggregate(mut paths: Vec<PathBuf>, config: &Config) -> Result<Results, Error> {
+    // biggest files first, so that one big file doesn't end up being parsed on its own at the end
+    paths.sort_by_cached_key(|path| std::cmp::Reverse(path.metadata().map_or(0, |m| m.len())));
+    let threads = config.threads.clamp(1, paths.len().max(1));
+    let queue = Mutex::new(paths.into_iter());
+    // set when a thread fails, so that the others stop taking files
+    let abort = AtomicBool::new(false);
+
+    let results = std::thread::scope(|s| {
+        (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut agg = Aggregator::new(config);
+                    while !abort.load(Ordering::Relaxed) {
+                        let Some(path) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        if let Err(err) = parse_file(&mut agg, &path, config) {
+                            abort.store(true, Ordering::Relaxed);
+                            return Err(err);
+                        }
+                    }
+                    Ok(agg)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let mut aggs = Vec::with_capacity(results.len());
+    for res in results {
+        match res {
+            Ok(agg) => aggs.push(Ok(agg)),
+            Err(Error::Parse(err)) => aggs.push(Err(err)),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(merge(aggs, config.policy)?)
+}
+
+fn parse_file(agg: &mut Aggregator, path: &Path, config: &Config) -> Result<(), Error> {
+    let with_path = |err: io::Error| io::Error::new(err.kind(), format!("{}: {err}", path.display()));
+    let reader = File::open(path).and_then(decompress).map_err(with_path)?;
+    agg.file = Some(path.into());
+    match read_parse(agg, reader, config) {
+        Err(Error::Io(err)) => Err(Error::Io(with_path(err))),
+        res => res,
+    }
+}
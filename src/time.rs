@@ -0,0 +1,101 @@
+use std::fmt::{Display, Formatter};
+
+// Timestamps are either unix seconds, or an ISO-8601 UTC date and time like 2024-06-01T13:45:00Z
+// (optionally with fractional seconds, which are truncated). Conversions between dates and days since
+// the epoch use the proleptic Gregorian calendar, see https://howardhinnant.github.io/date_algorithms.html
+
+pub(crate) fn parse_timestamp(s: &[u8]) -> Option<i64> {
+    match s {
+        [.., b'Z'] => parse_iso(&s[..s.len() - 1]),
+        _ => parse_unix(s),
+    }
+}
+
+fn parse_unix(s: &[u8]) -> Option<i64> {
+    let (negative, digits) = match s {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, s),
+    };
+    let secs = std::str::from_utf8(digits).ok()?;
+    if secs.is_empty() || !secs.bytes().all(|d| d.is_ascii_digit()) {
+        return None;
+    }
+    let secs: i64 = secs.parse().ok()?;
+    Some(if negative { -secs } else { secs })
+}
+
+// YYYY-MM-DDTHH:MM:SS[.fraction], the trailing Z already stripped
+fn parse_iso(s: &[u8]) -> Option<i64> {
+    let (s, fraction) = match memchr::memchr(b'.', s) {
+        Some(dot) => (&s[..dot], &s[dot + 1..]),
+        None => (s, &[][..]),
+    };
+    if fraction.iter().any(|d| !d.is_ascii_digit()) || s.len() != 19 {
+        return None;
+    }
+    let [y0, y1, y2, y3, b'-', m0, m1, b'-', d0, d1, b'T', h0, h1, b':', mi0, mi1, b':', s0, s1] = s else {
+        return None;
+    };
+    let number = |digits: &[u8]| digits.iter().try_fold(0, |n, &d| d.is_ascii_digit().then(|| n * 10 + (d - b'0') as i64));
+    let year = number(&[*y0, *y1, *y2, *y3])?;
+    let month = number(&[*m0, *m1])?;
+    let day = number(&[*d0, *d1])?;
+    let hour = number(&[*h0, *h1])?;
+    let minute = number(&[*mi0, *mi1])?;
+    let second = number(&[*s0, *s1])?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Renders unix seconds as an ISO-8601 UTC time, the way windows are shown in the outputs
+pub(crate) struct Timestamp(pub(crate) i64);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (year, month, day) = civil_from_days(self.0.div_euclid(86_400));
+        let secs = self.0.rem_euclid(86_400);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+            secs / 3_600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
@@ -1,142 +1,379 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::Read;
+use std::hash::Hash;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread::JoinHandle;
 
+use crate::error::{Error, ParseError, ParseErrorKind, Rejected};
+use crate::histogram::Histogram;
 use crate::ref_hash_map::RefHashMap;
+use crate::time::parse_timestamp;
+use crate::{Columns, Config, Policy, Results, Schema, Stations, Window};
 
-pub(crate) const BLOCK_SIZE: usize = 50_000;
-pub(crate) const N_BLOCKS: usize = 3;
-
-pub(crate) struct Buffers(Vec<ThreadBuffer>);
+// Blocks travel from the reader to whichever parser is free through `filled`, and are handed back to
+// the reader through `free` once they've been parsed. Both sides block on the channels when there's
+// nothing to do, instead of spinning over the blocks.
+pub(crate) struct Buffers {
+    filled_tx: mpsc::SyncSender<Block>,
+    filled_rx: Arc<Mutex<mpsc::Receiver<Block>>>,
+    free_tx: mpsc::Sender<Block>,
+    free_rx: mpsc::Receiver<Block>,
+    // set by a parser that hit an error under Policy::FailFast, so the reader stops early
+    abort: Arc<AtomicBool>,
+}
 
-pub(crate) type ThreadBuffer = Arc<[Mutex<[u8; BLOCK_SIZE]>; N_BLOCKS]>;
+pub(crate) struct Block {
+    buf: Box<[u8]>,
+    len: usize,
+    // position of buf[0] in the input
+    offset: u64,
+    // where a line starts that followed the block's data but was too long to fit in a block, which the
+    // reader skipped and the parser rejects
+    too_long: Option<u64>,
+}
 
 // HashBrown would work too, but it's slightly less reliable on my machine
 // than the modified version of std's Hashmap.
 
 // use hashbrown::HashMap;
 // pub(crate) type RefMap = HashMap<Box<u8>, Station>;
+// With a window, keys are the station followed by the window's start (i64, little-endian), see window_key.
 pub(crate) type RefMap = RefHashMap<Box<[u8]>, Station>;
 
-fn parse_worker(stop_rx: mpsc::Receiver<()>, thread_buffer: ThreadBuffer) -> RefMap {
-    let mut map = RefMap::with_capacity(512);
-    (0..N_BLOCKS)
-        .cycle()
-        .map_while(|i| {
-            (i != 0 || stop_rx.try_recv().is_err()).then(|| thread_buffer[i].try_lock().ok())
-        })
-        .flatten()
-        .filter_map(|buf| {
-            if buf[0] == 0 {
-                drop(buf);
-                std::thread::sleep(std::time::Duration::new(0, 50));
-                None
-            } else {
-                Some(buf)
-            }
-        })
-        .for_each(|mut buf| {
-            buf_parse(&mut map, buf.as_slice());
-            buf[0] = 0;
-        });
-    // last pass
-    for buf in (0..N_BLOCKS)
-        .map(|i| thread_buffer[i].lock().unwrap())
-        .filter(|buf| buf[0] != 0)
-    {
-        buf_parse(&mut map, buf.as_slice())
+fn parse_worker(
+    filled: Arc<Mutex<mpsc::Receiver<Block>>>,
+    free: mpsc::Sender<Block>,
+    abort: Arc<AtomicBool>,
+    config: Config,
+) -> Result<Aggregator, ParseError> {
+    let mut agg = Aggregator::new(&config);
+    // the lock is only held while waiting for a block, not while parsing it
+    while let Some(block) = recv_block(&filled) {
+        let parsed = agg
+            .parse(&block.buf[..block.len], block.offset)
+            .and_then(|()| block.too_long.map_or(Ok(()), |offset| agg.reject_too_long(offset)));
+        if let Err(err) = parsed {
+            abort.store(true, Ordering::Relaxed);
+            return Err(err);
+        }
+        // the reader is gone once it has sent the last block, so it's fine if nobody takes this back
+        let _ = free.send(block);
     }
-    map
+    Ok(agg)
 }
 
-fn buf_parse(map: &mut RefMap, buf: &[u8]) {
-    let mut start = 0;
+fn recv_block(filled: &Mutex<mpsc::Receiver<Block>>) -> Option<Block> {
+    filled.lock().unwrap().recv().ok()
+}
+
+// Per-thread aggregation state: the stations seen so far, and the lines that were rejected
+pub(crate) struct Aggregator {
+    map: RefMap,
+    rejected: Rejected,
+    policy: Policy,
+    // what a new station starts out as, apart from its first measurement: which extra statistics it tracks,
+    // and at what precision
+    empty: Station,
+    window: Option<Window>,
+    schema: Schema,
+    max_line_len: usize,
+    // scratch space for station + window keys
+    key: Vec<u8>,
+    // the shard file being parsed, if any, for error reporting
+    pub(crate) file: Option<Arc<Path>>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            map: RefMap::with_capacity(512),
+            rejected: Rejected::default(),
+            policy: config.policy,
+            empty: Station {
+                min: 0,
+                max: 0,
+                sum: 0,
+                count: 0,
+                sum_sq: config.variance.then_some(0),
+                // a dense histogram per station and window would be 16 KB for a handful of measurements
+                histogram: config.percentiles.then(|| match (config.schema.decimals, config.window) {
+                    (None, None) => Histogram::new(),
+                    _ => Histogram::sparse(),
+                }),
+                decimals: config.schema.decimals.unwrap_or(1),
+            },
+            window: config.window,
+            schema: config.schema,
+            max_line_len: config.max_line_len,
+            key: Vec::new(),
+            file: None,
+        }
+    }
 
-    for pos in memchr::Memchr::new(b'\n', buf) {
-        let bytes = &buf[start..pos];
-        start = pos + 1;
+    // offset is the position of buf[0] in the input, used to report where invalid lines are.
+    // Anything after the last newline is parsed as a line too: callers only pass that in for the
+    // end of the input, where it's a last line without a trailing newline.
+    pub(crate) fn parse(&mut self, buf: &[u8], offset: u64) -> Result<(), ParseError> {
+        let mut start = 0;
 
-        let line = Line::parse_bytes(bytes);
-        update(map, line);
+        for pos in memchr::Memchr::new(b'\n', buf) {
+            self.parse_line(&buf[start..pos], offset + start as u64)?;
+            start = pos + 1;
+        }
+        if start < buf.len() {
+            self.parse_line(&buf[start..], offset + start as u64)?;
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, bytes: &[u8], offset: u64) -> Result<(), ParseError> {
+        if bytes.len() > self.max_line_len {
+            return self.reject_too_long(offset);
+        }
+        // CRLF line endings
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        // station names end up as strs, so they're checked here, once per line
+        let line = Line::parse_bytes(bytes, &self.schema, self.window).and_then(|line| {
+            match std::str::from_utf8(line.station) {
+                Ok(_) => Ok(line),
+                Err(_) => Err(ParseErrorKind::InvalidUtf8),
+            }
+        });
+        match line {
+            Ok(Line {
+                station,
+                measurement,
+                window: None,
+            }) => update(&mut self.map, station, measurement, &self.empty),
+            Ok(Line {
+                station,
+                measurement,
+                window: Some(start),
+            }) => {
+                self.key.clear();
+                self.key.extend_from_slice(station);
+                self.key.extend_from_slice(&start.to_le_bytes());
+                update(&mut self.map, &self.key, measurement, &self.empty)
+            }
+            Err(kind) => self.reject(ParseError {
+                offset,
+                kind,
+                file: self.file.clone(),
+            })?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn reject_too_long(&mut self, offset: u64) -> Result<(), ParseError> {
+        self.reject(ParseError {
+            offset,
+            kind: ParseErrorKind::LineTooLong,
+            file: self.file.clone(),
+        })
+    }
+
+    fn reject(&mut self, err: ParseError) -> Result<(), ParseError> {
+        match self.policy {
+            Policy::FailFast => return Err(err),
+            Policy::Skip => self.rejected.count += 1,
+            Policy::Collect(limit) => self.rejected.record(err, limit),
+        }
+        Ok(())
     }
 }
 
-pub struct Parsers {
-    thread_handles: Vec<JoinHandle<RefMap>>,
-    stop_handles: Vec<mpsc::SyncSender<()>>,
+pub(crate) struct Parsers {
+    thread_handles: Vec<JoinHandle<Result<Aggregator, ParseError>>>,
+    policy: Policy,
 }
 
 impl Parsers {
-    pub(crate) fn start(parse_threads: usize, buffers: &Buffers) -> Self {
-        let (thread_handles, stop_handles): (Vec<_>, Vec<_>) = (0..parse_threads)
-            .map(|n| {
-                let (stop_tx, stop_rx) = mpsc::sync_channel::<()>(1);
-                let thread_buffer = buffers.get(n);
-                let map_handle = std::thread::spawn(move || parse_worker(stop_rx, thread_buffer));
-
-                (map_handle, stop_tx)
+    pub(crate) fn start(parse_threads: usize, buffers: &Buffers, config: &Config) -> Self {
+        let thread_handles = (0..parse_threads)
+            .map(|_| {
+                let filled = buffers.filled_rx.clone();
+                let free = buffers.free_tx.clone();
+                let abort = buffers.abort.clone();
+                let config = config.clone();
+                std::thread::spawn(move || parse_worker(filled, free, abort, config))
             })
-            .unzip();
+            .collect();
         Self {
             thread_handles,
-            stop_handles,
+            policy: config.policy,
         }
     }
 
-    pub(crate) fn join(self) -> Vec<(Box<str>, Station)> {
-        self.stop_handles
-            .into_iter()
-            .for_each(|s| s.send(()).unwrap());
-        let mut res = self
+    // the parsers stop once the reader has hung up, so this should only be called after read_worker
+    pub(crate) fn join(self) -> Result<Results, ParseError> {
+        let aggs = self
             .thread_handles
             .into_iter()
             .map(|h| h.join().unwrap())
-            .fold(
-                std::collections::HashMap::<Box<str>, Station>::with_capacity(512),
-                |mut map, like| {
-                    for (name, station2) in like.into_iter() {
-                        let name: Box<str> = unsafe { std::str::from_boxed_utf8_unchecked(name) };
-                        map.entry(name)
-                            .and_modify(|station1| station1.update(&station2))
-                            .or_insert(station2);
-                    }
-                    map
-                },
-            )
-            .into_iter()
-            .collect::<Vec<(Box<str>, Station)>>();
-        res.sort_unstable_by(|(n1, _), (n2, _)| n1.cmp(n2));
-        res
+            .collect::<Vec<_>>();
+        merge(aggs, self.policy)
     }
 }
 
-fn update(map: &mut RefMap, line: Line) {
-    map.entry_ref(line.station)
+// If any of the parsers failed, the earliest error in the input wins
+pub(crate) fn merge(
+    aggs: impl IntoIterator<Item = Result<Aggregator, ParseError>>,
+    policy: Policy,
+) -> Result<Results, ParseError> {
+    let aggs = aggs.into_iter().collect::<Vec<_>>();
+    if let Some(err) = aggs
+        .iter()
+        .filter_map(|agg| agg.as_ref().err())
+        .min_by_key(|err| err.offset)
+    {
+        return Err(err.clone());
+    }
+
+    let mut rejected = Rejected::default();
+    let mut merged = Merged::default();
+    for agg in aggs.into_iter().flatten() {
+        if let Policy::Collect(limit) = policy {
+            rejected.merge(agg.rejected, limit);
+        } else {
+            rejected.count += agg.rejected.count;
+        }
+        for (key, station) in agg.map.into_iter() {
+            let (name, window) = match agg.window {
+                Some(_) => window_key(&key),
+                None => (key, None),
+            };
+            let name = String::from_utf8(name.into_vec())
+                .expect("station names are checked in parse_line")
+                .into_boxed_str();
+            // every window counts towards the station's totals too
+            if window.is_some() {
+                merged.add(name.clone(), None, station.clone());
+            }
+            merged.add(name, window, station);
+        }
+    }
+    Ok(merged.finish(rejected))
+}
+
+// Splits a RefMap key back into the station and the start of its window
+fn window_key(key: &[u8]) -> (Box<[u8]>, Option<i64>) {
+    let (name, start) = key.split_at(key.len() - 8);
+    (name.into(), Some(i64::from_le_bytes(start.try_into().unwrap())))
+}
+
+// Stations, and with windows the stations of every window, combined by name from several aggregations
+#[derive(Default)]
+pub(crate) struct Merged {
+    stations: HashMap<Box<str>, Station>,
+    windows: HashMap<(i64, Box<str>), Station>,
+}
+
+impl Merged {
+    pub(crate) fn add(&mut self, name: Box<str>, window: Option<i64>, station: Station) {
+        match window {
+            None => merge_into(&mut self.stations, name, station),
+            Some(start) => merge_into(&mut self.windows, (start, name), station),
+        }
+    }
+
+    // Partials made with and without --variance or --percentiles can cover different stations. Only what every
+    // station tracked is kept, so that they all have the same statistics.
+    pub(crate) fn finish(mut self, rejected: Rejected) -> Results {
+        let all = || self.stations.values().chain(self.windows.values());
+        let variance = all().all(|station| station.sum_sq.is_some());
+        let percentiles = all().all(|station| station.histogram.is_some());
+        for station in self.stations.values_mut().chain(self.windows.values_mut()) {
+            if !variance {
+                station.sum_sq = None;
+            }
+            if !percentiles {
+                station.histogram = None;
+            }
+        }
+        let mut stations = self.stations.into_iter().collect::<Stations>();
+        stations.sort_unstable_by(|(n1, _), (n2, _)| n1.cmp(n2));
+        Results {
+            stations,
+            windows: group_windows(self.windows),
+            rejected,
+        }
+    }
+}
+
+fn merge_into<K: Hash + Eq>(map: &mut HashMap<K, Station>, key: K, station: Station) {
+    map.entry(key)
+        .and_modify(|merged| merged.update(&station))
+        .or_insert(station);
+}
+
+// Windows in ascending order, each with its stations sorted by name
+fn group_windows(windows: HashMap<(i64, Box<str>), Station>) -> Vec<(i64, Stations)> {
+    let mut windows = windows.into_iter().collect::<Vec<_>>();
+    windows.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+    let mut grouped = Vec::<(i64, Stations)>::new();
+    for ((start, name), station) in windows {
+        match grouped.last_mut() {
+            Some((last, stations)) if *last == start => stations.push((name, station)),
+            _ => grouped.push((start, vec![(name, station)])),
+        }
+    }
+    grouped
+}
+
+fn update(map: &mut RefMap, key: &[u8], measurement: i32, empty: &Station) {
+    map.entry_ref(key)
         .and_modify(|station| {
-            if line.measurement > station.max {
-                station.max = line.measurement
+            if measurement > station.max {
+                station.max = measurement
             } else {
-                station.min = station.min.min(line.measurement)
+                station.min = station.min.min(measurement)
             }
-            station.sum += line.measurement;
+            station.sum += measurement as i64;
             station.count += 1;
+            if let Some(sum_sq) = &mut station.sum_sq {
+                *sum_sq += square(measurement);
+            }
+            if let Some(histogram) = &mut station.histogram {
+                histogram.add(measurement);
+            }
         })
-        .or_insert_with(|| Station {
-            min: line.measurement,
-            max: line.measurement,
-            sum: line.measurement,
-            count: 1,
+        .or_insert_with(|| {
+            let mut station = empty.clone();
+            station.min = measurement;
+            station.max = measurement;
+            station.sum = measurement as i64;
+            station.count = 1;
+            if let Some(sum_sq) = &mut station.sum_sq {
+                *sum_sq = square(measurement);
+            }
+            if let Some(histogram) = &mut station.histogram {
+                histogram.add(measurement);
+            }
+            station
         });
 }
 
-// min/max/sum 10x larger than true values
+fn square(measurement: i32) -> u128 {
+    (measurement.unsigned_abs() as u128).pow(2)
+}
+
+// min/max/sum scaled by 10^decimals (10x larger than true values by default). A single measurement always
+// fits in an i32, but a billion of them don't, so the sum and count are 64-bit.
+#[derive(Clone)]
 pub struct Station {
-    min: i32,
-    max: i32,
-    sum: i32,
-    count: u32,
+    pub(crate) min: i32,
+    pub(crate) max: i32,
+    pub(crate) sum: i64,
+    pub(crate) count: u64,
+    // sum of squared scaled measurements, only tracked when the variance is asked for
+    pub(crate) sum_sq: Option<u128>,
+    // count per measurement, only tracked when percentiles are asked for
+    pub(crate) histogram: Option<Histogram>,
+    // number of decimals the measurements were parsed with
+    pub(crate) decimals: u8,
 }
 
 impl Station {
@@ -145,6 +382,100 @@ impl Station {
         self.max = self.max.max(other.max);
         self.sum += other.sum;
         self.count += other.count;
+        self.sum_sq = self.sum_sq.zip(other.sum_sq).map(|(a, b)| a + b);
+        // only known if both sides tracked it, like sum_sq
+        match (&mut self.histogram, &other.histogram) {
+            (Some(histogram), Some(other)) => histogram.merge(other),
+            _ => self.histogram = None,
+        }
+    }
+
+    pub fn min(&self) -> f64 {
+        self.unscale(self.min as i64)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.unscale(self.max as i64)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.unscale(self.sum) / self.count as f64
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.unscale(self.sum)
+    }
+
+    /// Number of decimals the measurements were parsed with: 1 unless `Schema::decimals` says otherwise.
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Population variance, if it was tracked (`Config::variance`).
+    pub fn variance(&self) -> Option<f64> {
+        // n * sum(x^2) - sum(x)^2 is exact in integers, and can't be negative
+        let count = self.count as i128;
+        let spread = count * self.sum_sq? as i128 - (self.sum as i128).pow(2);
+        Some(spread as f64 / (count * count) as f64 * 10f64.powi(-2 * self.decimals as i32))
+    }
+
+    /// Population standard deviation, if the variance was tracked (`Config::variance`).
+    pub fn std_dev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    /// The `p`th percentile (0.0 to 1.0) by nearest rank, if percentiles were tracked (`Config::percentiles`).
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.percentile_scaled(p).map(|scaled| self.unscale(scaled as i64))
+    }
+
+    pub(crate) fn percentile_scaled(&self, p: f64) -> Option<i32> {
+        Some(self.histogram.as_ref()?.percentile(p, self.count))
+    }
+
+    /// (measurement, count) for every distinct measurement in ascending order, if percentiles were tracked
+    /// (`Config::percentiles`).
+    pub fn histogram(&self) -> Option<impl Iterator<Item = (f64, u64)> + '_> {
+        let buckets = self.histogram.as_ref()?.buckets();
+        Some(buckets.map(|(scaled, n)| (self.unscale(scaled as i64), n)))
+    }
+
+    // The mean at the measurements' precision, rounded half up like the reference implementation's Math.round.
+    // floor(sum / count + 1/2) == floor((2 * sum + count) / (2 * count)), all in integers.
+    pub(crate) fn mean_scaled(&self) -> i64 {
+        let count = self.count as i128;
+        (2 * self.sum as i128 + count).div_euclid(2 * count) as i64
+    }
+
+    fn unscale(&self, scaled: i64) -> f64 {
+        scaled as f64 * 10f64.powi(-(self.decimals as i32))
+    }
+
+    pub(crate) fn fixed(&self, scaled: i64) -> Fixed {
+        Fixed(scaled, self.decimals)
+    }
+}
+
+// the percentiles that are shown in the outputs
+pub(crate) const PERCENTILES: [(&str, f64); 3] = [("median", 0.5), ("p95", 0.95), ("p99", 0.99)];
+
+// Exact decimal rendering of a value scaled by 10^decimals, without going through floats.
+// There's no negative zero in integers, so this never prints "-0.0".
+pub(crate) struct Fixed(pub(crate) i64, pub(crate) u8);
+
+impl Display for Fixed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = 10u64.pow(self.1 as u32);
+        match self.1 {
+            0 => write!(f, "{sign}{abs}"),
+            decimals => write!(f, "{sign}{}.{:0width$}", abs / scale, abs % scale, width = decimals as usize),
+        }
     }
 }
 
@@ -152,97 +483,312 @@ impl Display for Station {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{:.1}/{:.1}/{:.1}",
-            (self.min as f32) * 0.1,
-            ((self.sum as f32) * 0.1) / (self.count as f32),
-            (self.max as f32) * 0.1
-        )
+            "{}/{}/{}",
+            self.fixed(self.min as i64),
+            self.fixed(self.mean_scaled()),
+            self.fixed(self.max as i64)
+        )?;
+        if let Some(std_dev) = self.std_dev() {
+            write!(f, "/{std_dev:.0$}", self.decimals as usize)?;
+        }
+        for (_, p) in PERCENTILES {
+            if let Some(scaled) = self.percentile_scaled(p) {
+                write!(f, "/{}", self.fixed(scaled as i64))?;
+            }
+        }
+        Ok(())
     }
 }
 
 struct Line<'a> {
     station: &'a [u8],
     measurement: i32,
+    // start of the line's window in unix seconds, for timestamped input
+    window: Option<i64>,
 }
 
 impl<'a> Line<'a> {
-    fn parse_bytes(s: &'a [u8]) -> Self {
-        let colon_pos = memchr::memrchr(b';', s).unwrap();
-        let fraction: i32 = (*s.last().unwrap() as char).to_digit(10).unwrap() as i32;
-        let mut num = s[colon_pos + 2..s.len() - 2]
-            .iter()
-            .map(|d| (*d as char).to_digit(10).unwrap() as i32)
-            .rev()
-            .enumerate()
-            .map(|(pow, num)| num * 10_i32.pow((pow + 1) as u32))
-            .sum::<i32>()
-            + fraction;
-
-        let first_num_char = s[colon_pos + 1];
-        if first_num_char == b'-' {
-            num *= -1;
-        } else {
-            num += (first_num_char as char).to_digit(10).unwrap() as i32
-                * 10_i32.pow((s.len() - colon_pos - 3) as u32)
+    // With a window, there's a timestamp between the station and the measurement
+    fn parse_bytes(s: &'a [u8], schema: &Schema, window: Option<Window>) -> Result<Self, ParseErrorKind> {
+        if let Some(columns) = &schema.columns {
+            return Self::parse_columns(s, schema, columns, window);
+        }
+        let delimiter = schema.delimiter;
+        let colon_pos = memchr::memrchr(delimiter, s).ok_or(ParseErrorKind::MissingSeparator)?;
+        let measurement = parse_measurement(&s[colon_pos + 1..], schema.decimals)?;
+        let Some(window) = window else {
+            return Ok(Line {
+                station: &s[..colon_pos],
+                measurement,
+                window: None,
+            });
+        };
+        let station_end = memchr::memrchr(delimiter, &s[..colon_pos]).ok_or(ParseErrorKind::MissingSeparator)?;
+        let start = parse_timestamp(&s[station_end + 1..colon_pos])
+            .and_then(|timestamp| window_start(timestamp, window))
+            .ok_or(ParseErrorKind::InvalidTimestamp)?;
+        Ok(Line {
+            station: &s[..station_end],
+            measurement,
+            window: Some(start),
+        })
+    }
+
+    // Slower than the default layout, as every column up to the last one that's needed is split off
+    fn parse_columns(
+        s: &'a [u8],
+        schema: &Schema,
+        columns: &Columns,
+        window: Option<Window>,
+    ) -> Result<Self, ParseErrorKind> {
+        let delimiter = schema.delimiter;
+        let (mut station, mut measurement, mut timestamp) = (None, None, None);
+        let timestamp_column = window.and(columns.timestamp);
+        for (i, column) in s.split(|&b| b == delimiter).enumerate() {
+            if i == columns.station {
+                station = Some(column);
+            }
+            if i == columns.measurement {
+                measurement = Some(column);
+            }
+            if Some(i) == timestamp_column {
+                timestamp = Some(column);
+            }
+            if station.is_some() && measurement.is_some() && timestamp.is_some() == timestamp_column.is_some() {
+                break;
+            }
+        }
+        let (Some(station), Some(measurement)) = (station, measurement) else {
+            return Err(ParseErrorKind::MissingColumn);
+        };
+        let measurement = parse_measurement(measurement, schema.decimals)?;
+        let window = match window {
+            Some(window) => {
+                let timestamp = timestamp.ok_or(ParseErrorKind::MissingColumn)?;
+                let start = parse_timestamp(timestamp).and_then(|timestamp| window_start(timestamp, window));
+                Some(start.ok_or(ParseErrorKind::InvalidTimestamp)?)
+            }
+            None => None,
+        };
+        Ok(Line {
+            station,
+            measurement,
+            window,
+        })
+    }
+}
+
+// None if the start of the window is before i64::MIN
+fn window_start(timestamp: i64, window: Window) -> Option<i64> {
+    timestamp.div_euclid(window.seconds()).checked_mul(window.seconds())
+}
+
+fn parse_measurement(s: &[u8], decimals: Option<u8>) -> Result<i32, ParseErrorKind> {
+    match decimals {
+        None => parse_tenths(s).ok_or(ParseErrorKind::InvalidMeasurement),
+        Some(decimals) => parse_decimal(s, decimals),
+    }
+}
+
+// Measurements have one to three integer digits and exactly one fractional digit, optionally negative
+fn parse_tenths(s: &[u8]) -> Option<i32> {
+    let (negative, s) = match s {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, s),
+    };
+    let [int @ .., b'.', fraction] = s else {
+        return None;
+    };
+    if int.is_empty() || int.len() > 3 {
+        return None;
+    }
+    let num = int
+        .iter()
+        .chain(std::iter::once(fraction))
+        .try_fold(0, |num, &d| Some(num * 10 + digit(d)?))?;
+    Some(if negative { -num } else { num })
+}
+
+// An optionally signed integer, or a decimal with up to `decimals` fractional digits, scaled by 10^decimals
+fn parse_decimal(s: &[u8], decimals: u8) -> Result<i32, ParseErrorKind> {
+    let (negative, s) = match s {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, s),
+    };
+    let (int, fraction) = match memchr::memchr(b'.', s) {
+        Some(dot) if dot + 1 < s.len() => (&s[..dot], &s[dot + 1..]),
+        Some(_) => return Err(ParseErrorKind::InvalidMeasurement),
+        None => (s, &[][..]),
+    };
+    if int.is_empty() {
+        return Err(ParseErrorKind::InvalidMeasurement);
+    }
+    let num = int
+        .iter()
+        .chain(fraction)
+        .try_fold(0i32, |num, &d| num.checked_mul(10)?.checked_add(digit(d)?))
+        .ok_or(ParseErrorKind::InvalidMeasurement)?;
+    let missing = (decimals as usize)
+        .checked_sub(fraction.len())
+        .ok_or(ParseErrorKind::TooManyDecimals)?;
+    let num = 10i32
+        .checked_pow(missing as u32)
+        .and_then(|scale| num.checked_mul(scale))
+        .ok_or(ParseErrorKind::InvalidMeasurement)?;
+    Ok(if negative { -num } else { num })
+}
+
+fn digit(d: u8) -> Option<i32> {
+    d.is_ascii_digit().then(|| (d - b'0') as i32)
+}
+
+pub(crate) fn read_worker<R: Read>(buffers: Buffers, mut reader: R, config: &Config) -> Result<(), Error> {
+    let Buffers {
+        filled_tx,
+        free_rx,
+        free_tx,
+        abort,
+        ..
+    } = buffers;
+    // if all parsers have stopped, there's nobody left to hand blocks back
+    drop(free_tx);
+    let mut remainder = Vec::with_capacity(config.max_line_len);
+    let mut offset = 0;
+    for mut block in free_rx.iter() {
+        if abort.load(Ordering::Relaxed) {
+            break;
+        }
+        block.buf[..remainder.len()].copy_from_slice(&remainder);
+        block.offset = offset;
+        block.too_long = None;
+        let filled = fill(&mut reader, &mut block.buf, remainder.len())?;
+        if filled < block.buf.len() {
+            block.len = filled;
+            filled_tx.send(block).unwrap();
+            break;
         }
-        Line {
-            station: &s[..colon_pos],
-            measurement: num,
+
+        remainder.clear();
+        match carry_start(&block.buf, config.max_line_len) {
+            Some(carry) => {
+                remainder.extend_from_slice(&block.buf[carry..]);
+                block.len = carry;
+                offset += carry as u64;
+            }
+            None => {
+                block.len = line_start(&block.buf);
+                block.too_long = Some(offset + block.len as u64);
+                // whatever follows the long line is carried over instead
+                remainder.resize(block.buf.len(), 0);
+                let (skipped, rest) = skip_line(&mut reader, &mut remainder)?;
+                remainder.truncate(rest);
+                offset += block.buf.len() as u64 + skipped;
+            }
         }
+        filled_tx.send(block).unwrap();
     }
+    Ok(())
 }
 
-pub fn read_worker(thread_buffers: Buffers, mut file: File) {
-    let mut remainder = [0; 50];
-    let mut remainder_size = 0;
-    for thread_locks in thread_buffers.0.iter().cycle() {
-        for mut buf in thread_locks
-            .iter()
-            .filter_map(|l| l.try_lock().ok())
-            .filter(|b| b[0] == 0)
-        {
-            let mut start = remainder_size;
-            buf[..start].copy_from_slice(&remainder[..remainder_size]);
-            loop {
-                let read = file.read(&mut buf[start..]).unwrap();
-                if read == 0 {
-                    break;
-                }
-                start += read;
+// Single-threaded fallback (and per-shard parse): the same block-by-block parse, but inline on the
+// reading thread, carrying the partial last line over to the front of the (only) block.
+pub(crate) fn read_parse<R: Read>(agg: &mut Aggregator, mut reader: R, config: &Config) -> Result<(), Error> {
+    let mut buf = vec![0; config.block_size];
+    let mut start = 0;
+    let mut offset = 0;
+    loop {
+        let filled = fill(&mut reader, &mut buf, start)?;
+        if filled < buf.len() {
+            agg.parse(&buf[..filled], offset)?;
+            return Ok(());
+        }
+
+        match carry_start(&buf, config.max_line_len) {
+            Some(carry) => {
+                agg.parse(&buf[..carry], offset)?;
+                buf.copy_within(carry.., 0);
+                start = buf.len() - carry;
+                offset += carry as u64;
             }
-            if start < BLOCK_SIZE {
-                buf[start..].fill(0);
-                return;
+            None => {
+                let line = line_start(&buf);
+                agg.parse(&buf[..line], offset)?;
+                agg.reject_too_long(offset + line as u64)?;
+                let (skipped, rest) = skip_line(&mut reader, &mut buf)?;
+                start = rest;
+                offset += buf.len() as u64 + skipped;
             }
+        }
+    }
+}
+
+// Read until the buffer is full or the reader is exhausted, returning the number of bytes in the buffer
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8], mut start: usize) -> io::Result<usize> {
+    loop {
+        let read = match reader.read(&mut buf[start..]) {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if read == 0 {
+            return Ok(start);
+        }
+        start += read;
+    }
+}
 
-            let last_nl = (BLOCK_SIZE - 50)
-                + memchr::memrchr(b'\n', &buf[BLOCK_SIZE - 50..]).expect("Missing newline in file");
+// Where the partial line at the end of a full block starts, which is carried over to the next block.
+// Only the newline just before the last max_line_len bytes and those bytes themselves are searched:
+// if there's no newline there, the line is too long, and there's nothing to carry over.
+fn carry_start(buf: &[u8], max_line_len: usize) -> Option<usize> {
+    let window = buf.len().saturating_sub(max_line_len + 1);
+    memchr::memrchr(b'\n', &buf[window..]).map(|nl| window + nl + 1)
+}
 
-            let rem = &buf[last_nl + 1..];
-            remainder_size = rem.len();
-            remainder[..remainder_size].copy_from_slice(rem);
+// Just after the last newline, or 0 without one
+fn line_start(buf: &[u8]) -> usize {
+    memchr::memrchr(b'\n', buf).map_or(0, |nl| nl + 1)
+}
+
+// Reads past the rest of a line that's too long to parse. Returns how many bytes were skipped, including
+// the newline, and how many bytes after it were read into the start of buf.
+fn skip_line<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<(u64, usize)> {
+    let mut skipped = 0;
+    loop {
+        let filled = fill(reader, buf, 0)?;
+        if let Some(nl) = memchr::memchr(b'\n', &buf[..filled]) {
+            buf.copy_within(nl + 1..filled, 0);
+            return Ok((skipped + nl as u64 + 1, filled - nl - 1));
+        }
+        skipped += filled as u64;
+        if filled < buf.len() {
+            return Ok((skipped, 0));
         }
     }
 }
 
 impl Buffers {
-    pub(crate) fn new(parse_threads: usize) -> Self {
-        Self(
-            (0..parse_threads)
-                .map(|_| {
-                    Arc::new(
-                        (0..N_BLOCKS)
-                            .map(|_| Mutex::new([0; BLOCK_SIZE]))
-                            .collect::<Vec<_>>()
-                            .try_into()
-                            .unwrap(),
-                    )
+    pub(crate) fn new(parse_threads: usize, config: &Config) -> Self {
+        let n_blocks = parse_threads * config.blocks_per_thread.max(1);
+        let (filled_tx, filled_rx) = mpsc::sync_channel(n_blocks);
+        let (free_tx, free_rx) = mpsc::channel();
+        for _ in 0..n_blocks {
+            free_tx
+                .send(Block {
+                    buf: vec![0; config.block_size].into_boxed_slice(),
+                    len: 0,
+                    offset: 0,
+                    too_long: None,
                 })
-                .collect::<Vec<_>>(),
-        )
-    }
-
-    pub(crate) fn get(&self, nth: usize) -> ThreadBuffer {
-        self.0[nth].clone()
+                .unwrap();
+        }
+        Self {
+            filled_tx,
+            filled_rx: Arc::new(Mutex::new(filled_rx)),
+            free_tx,
+            free_rx,
+            abort: Arc::new(AtomicBool::new(false)),
+        }
     }
 }
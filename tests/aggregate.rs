@@ -0,0 +1,306 @@
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use brc::output::Format;
+use brc::partial::{merge, read_partial, write_partial};
+use brc::{
+    Columns, Config, Error, ParseErrorKind, Policy, Results, aggregate, aggregate_follow, aggregate_mmap,
+    aggregate_resumable,
+};
+
+use crate::common::{config, output, render, temp_path};
+
+mod common;
+
+// Same input through the fused single-threaded loop, the reader thread + parsers, and mmap
+fn run_all(input: &[u8]) -> String {
+    let fused = render(&aggregate(input, &config(1)).unwrap());
+    let threaded = render(&aggregate(input, &config(3)).unwrap());
+    assert_eq!(fused, threaded);
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let n = FILES.fetch_add(1, Ordering::Relaxed);
+    let path = temp_path(&format!("test-{n}"));
+    std::fs::File::create(&path).unwrap().write_all(input).unwrap();
+    let mapped = render(&aggregate_mmap(&std::fs::File::open(&path).unwrap(), &config(3)).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(fused, mapped);
+
+    fused
+}
+
+// A few hundred KB of input, so that it spans several blocks and the last one is a short one
+fn big_input(line_end: &str, trailing: bool) -> Vec<u8> {
+    let mut input = String::new();
+    for i in 0..30_000 {
+        if i != 0 {
+            input.push_str(line_end);
+        }
+        input.push_str(&format!("station {};{}.{}", i % 7, i % 100 - 50, i % 10));
+    }
+    if trailing {
+        input.push_str(line_end);
+    }
+    input.into_bytes()
+}
+
+#[test]
+fn crlf_line_endings() {
+    assert_eq!(run_all(b"a;1.0\r\nb;-2.5\r\na;3.0\r\n"), "a=1.0/2.0/3.0, b=-2.5/-2.5/-2.5");
+}
+
+#[test]
+fn missing_trailing_newline() {
+    assert_eq!(run_all(b"a;1.0\nb;-2.5\na;3.0"), "a=1.0/2.0/3.0, b=-2.5/-2.5/-2.5");
+    assert_eq!(run_all(b"a;1.0\r\nb;-2.5\r\na;3.0"), "a=1.0/2.0/3.0, b=-2.5/-2.5/-2.5");
+}
+
+#[test]
+fn final_block_across_line_endings() {
+    let expected = run_all(&big_input("\n", true));
+    assert_eq!(run_all(&big_input("\n", false)), expected);
+    assert_eq!(run_all(&big_input("\r\n", true)), expected);
+    assert_eq!(run_all(&big_input("\r\n", false)), expected);
+}
+
+// The sum of one station's tenths doesn't fit in an i32, both within one thread and when merging threads
+#[test]
+fn sum_beyond_i32() {
+    let input = b"hot;99.9\nhot;99.7\n".repeat(1_100_000);
+    for threads in [1, 3] {
+        let res = aggregate(&input[..], &config(threads)).unwrap();
+        let (name, station) = &res.stations[0];
+        assert_eq!(&**name, "hot");
+        assert_eq!(station.count(), 2_200_000);
+        assert!(station.sum() > i32::MAX as f64 * 0.1);
+        assert!((station.sum() - 219_560_000.0).abs() < 1e-3);
+        assert!((station.mean() - 99.8).abs() < 1e-9);
+        assert_eq!(render(&res), "hot=99.7/99.8/99.9");
+    }
+}
+
+// Means are rounded half up (towards positive infinity) like the reference implementation, and never
+// print as -0.0
+#[test]
+fn rounding_matches_reference() {
+    let input = b"\
+up;1.0\nup;1.1\n\
+down;-1.0\ndown;-1.1\n\
+zero;-0.1\nzero;0.0\nzero;0.0\n\
+negzero;-0.0\n\
+third;2.5\nthird;2.6\nthird;2.6\n\
+small;-0.1\nsmall;-0.1\nsmall;0.1\nsmall;0.0\nsmall;0.0\nsmall;0.0\n\
+wide;-99.9\nwide;99.9\nwide;12.3\n";
+    assert_eq!(
+        run_all(input),
+        "down=-1.1/-1.0/-1.0, negzero=0.0/0.0/0.0, small=-0.1/0.0/0.1, third=2.5/2.6/2.6, \
+         up=1.0/1.1/1.1, wide=-99.9/4.1/99.9, zero=-0.1/0.0/0.0"
+    );
+}
+
+// A run that fails part way leaves a checkpoint behind, and resuming once the input is fixed gives the same
+// results as a run that never failed
+#[test]
+fn resume_from_checkpoint() {
+    let input = big_input("\n", true);
+    let mut broken = input.clone();
+    let bad = broken.len() / 3 * 2;
+    let bad = bad + broken[bad..].iter().position(|&b| b == b';').unwrap();
+    broken[bad] = b'#';
+
+    let path = temp_path("resume");
+    let checkpoint = temp_path("resume.brck");
+    std::fs::write(&path, &broken).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let err = aggregate_resumable(&file, &checkpoint, 20_000, false, &config(3));
+    assert!(matches!(err, Err(Error::Parse(err)) if err.offset < bad as u64 && err.offset > bad as u64 - 20));
+    assert!(checkpoint.exists());
+
+    std::fs::write(&path, &input).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let resumed = render(&aggregate_resumable(&file, &checkpoint, 20_000, true, &config(3)).unwrap());
+    assert!(!checkpoint.exists());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(resumed, run_all(&input));
+}
+
+// Lines longer than max_line_len are rejected wherever they are, through the policy like any other invalid
+// line, and the lines after them are still aggregated. Some are longer than a block.
+#[test]
+fn long_lines() {
+    let mut input = String::new();
+    let mut long = Vec::new();
+    for i in 0..300 {
+        let name = match i % 7 {
+            0 => "x".repeat(16 + i % 5),
+            3 if i % 2 == 0 => "y".repeat(150 + i),
+            _ => format!("s{}", i % 3),
+        };
+        // a \r counts towards the length, only the \n doesn't
+        let line = format!("{name};{}.0{}", i % 10, if i % 4 == 0 { "\r" } else { "" });
+        if line.len() > 20 {
+            long.push(input.len() as u64);
+        }
+        input.push_str(&line);
+        input.push('\n');
+    }
+    let long_lines = |config: &Config, res: Result<Results, Error>| {
+        let res = res.unwrap();
+        assert_eq!(res.rejected.count, long.len() as u64, "{config:?}");
+        assert!(res.rejected.first.iter().all(|err| err.kind == ParseErrorKind::LineTooLong));
+        (render(&res), res.rejected.first.iter().map(|err| err.offset).collect::<Vec<_>>())
+    };
+
+    let path = temp_path("long-lines");
+    std::fs::write(&path, &input).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let mut expected = None;
+    for threads in [1, 3] {
+        let config = Config {
+            policy: Policy::Collect(1_000),
+            block_size: 64,
+            max_line_len: 20,
+            ..config(threads)
+        };
+        let runs = [
+            long_lines(&config, aggregate(input.as_bytes(), &config)),
+            long_lines(&config, aggregate_mmap(&file, &config)),
+        ];
+        for (stations, offsets) in runs {
+            assert_eq!(offsets, long);
+            assert_eq!(expected.get_or_insert_with(|| stations.clone()), &stations);
+        }
+
+        let err = aggregate(input.as_bytes(), &Config { policy: Policy::FailFast, ..config });
+        assert!(matches!(err, Err(Error::Parse(err)) if err.offset == long[0]));
+    }
+    std::fs::remove_file(&path).unwrap();
+    assert!(expected.unwrap().starts_with("s0="));
+}
+
+// Population variance by hand: a is 1.0, 2.0 and 4.0 (mean 7/3, variance 14/9), b is -1.5 and 1.5 (mean 0,
+// variance 2.25). Repeated, so that the sums of squares of several parsers and partials are merged.
+#[test]
+fn variance() {
+    let input = "a;1.0\nb;-1.5\na;2.0\nb;1.5\na;4.0\n".repeat(1_000);
+    let close = |value: Option<f64>, expected: f64| (value.unwrap() - expected).abs() < 1e-9;
+    for threads in [1, 3] {
+        let config = Config {
+            variance: true,
+            block_size: 64,
+            ..config(threads)
+        };
+        let res = aggregate(input.as_bytes(), &config).unwrap();
+        let (a, b) = (&res.stations[0].1, &res.stations[1].1);
+        assert!(close(a.variance(), 14.0 / 9.0));
+        assert!(close(a.std_dev(), (14.0f64 / 9.0).sqrt()));
+        assert!(close(b.variance(), 2.25));
+        assert!(close(b.std_dev(), 1.5));
+        assert_eq!(render(&res), "a=1.0/2.3/4.0/1.2, b=-1.5/0.0/1.5/1.5");
+
+        let json = output(input.as_bytes(), &config, Format::Json);
+        let (variance, std_dev) = (a.variance().unwrap(), a.std_dev().unwrap());
+        assert!(json.contains(&format!("\"sum\": 7000.0, \"variance\": {variance}, \"stddev\": {std_dev}}}")));
+        let csv = output(input.as_bytes(), &config, Format::Csv);
+        assert!(csv.starts_with("station,min,mean,max,count,sum,variance,stddev\n"));
+        assert!(csv.contains(&format!("\na,1.0,2.3,4.0,3000,7000.0,{variance},{std_dev}\n")));
+
+        // split into partials somewhere in the middle of the five lines above
+        let split = input[10_000..].find("b;1.5").unwrap() + 10_000;
+        let mut partials = Vec::new();
+        for part in [&input[..split], &input[split..]] {
+            let mut partial = Vec::new();
+            write_partial(&mut partial, &aggregate(part.as_bytes(), &config).unwrap()).unwrap();
+            partials.push(read_partial(&partial[..]).unwrap());
+        }
+        let merged = merge(partials).unwrap();
+        assert!(close(merged.stations[0].1.variance(), 14.0 / 9.0));
+        assert!(close(merged.stations[1].1.variance(), 2.25));
+    }
+    let res = aggregate(input.as_bytes(), &config(1)).unwrap();
+    assert_eq!(res.stations[0].1.variance(), None);
+    assert_eq!(res.stations[0].1.std_dev(), None);
+}
+
+// Station names have to be UTF-8, invalid ones are rejected like any other malformed line
+#[test]
+fn invalid_utf8() {
+    let input = b"ab\xff\xfe;1.0\nok;2.0\n\xc3;3.0\n";
+    for threads in [1, 3] {
+        let collect = Config {
+            policy: Policy::Collect(10),
+            ..config(threads)
+        };
+        let res = aggregate(&input[..], &collect).unwrap();
+        assert_eq!(render(&res), "ok=2.0/2.0/2.0");
+        let rejected: Vec<_> = res.rejected.first.iter().map(|err| (err.offset, err.kind)).collect();
+        assert_eq!(rejected, [(0, ParseErrorKind::InvalidUtf8), (16, ParseErrorKind::InvalidUtf8)]);
+
+        let err = aggregate(&input[..], &config(threads));
+        assert!(matches!(err, Err(Error::Parse(err)) if err.kind == ParseErrorKind::InvalidUtf8 && err.offset == 0));
+    }
+}
+
+// A checkpoint only resumes a run with the same options, including how lines are laid out
+#[test]
+fn resume_with_other_schema() {
+    let path = temp_path("schema");
+    let checkpoint = temp_path("schema.brck");
+    std::fs::write(&path, "a;1.0\nb;2.0\nbad\n").unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    assert!(matches!(aggregate_resumable(&file, &checkpoint, 1, false, &config(1)), Err(Error::Parse(_))));
+
+    let mut other = config(1);
+    other.schema.columns = Some(Columns {
+        station: 0,
+        measurement: 1,
+        timestamp: None,
+    });
+    let err = aggregate_resumable(&file, &checkpoint, 1, true, &other);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&checkpoint).unwrap();
+    assert!(matches!(err, Err(Error::Checkpoint(err)) if err.to_string().contains("--columns")));
+}
+
+// A line that's only partly written isn't aggregated until its newline is there, and then only once
+#[test]
+fn follow_partial_lines() {
+    let path = temp_path("follow");
+    std::fs::write(&path, "a;1.0\nb;2.").unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let mut snapshots = Vec::new();
+    aggregate_follow(&file, Duration::from_millis(10), &config(1), |res| {
+        snapshots.push(render(res));
+        let append = match snapshots.len() {
+            1 => "0\nc;3",
+            2 => ".0\n",
+            _ => return ControlFlow::Break(()),
+        };
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(append.as_bytes()).unwrap();
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        snapshots,
+        ["a=1.0/1.0/1.0", "a=1.0/1.0/1.0, b=2.0/2.0/2.0", "a=1.0/1.0/1.0, b=2.0/2.0/2.0, c=3.0/3.0/3.0"]
+    );
+}
+
+// Measurements beyond ±99.9 are valid, and count as themselves in the percentiles and histograms
+#[test]
+fn percentiles_beyond_99_9() {
+    let input = b"a;1.0\na;150.0\na;150.0\nb;-999.9\nb;5.0\n";
+    for threads in [1, 3] {
+        let config = Config {
+            percentiles: true,
+            ..config(threads)
+        };
+        let res = aggregate(&input[..], &config).unwrap();
+        let histogram = res.stations[0].1.histogram().unwrap().collect::<Vec<_>>();
+        assert_eq!(histogram, [(1.0, 1), (150.0, 2)]);
+        assert_eq!(render(&res), "a=1.0/100.3/150.0/150.0/150.0/150.0, b=-999.9/-497.4/5.0/-999.9/5.0/5.0");
+    }
+}
@@ -0,0 +1,35 @@
+// Helpers shared by the integration tests, not all of which use every one of them
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use brc::output::{Format, write_results};
+use brc::{Config, Results, aggregate};
+
+pub fn config(threads: usize) -> Config {
+    Config {
+        threads,
+        ..Config::default()
+    }
+}
+
+// The stations like in the text output, without the braces
+pub fn render(res: &Results) -> String {
+    res.stations
+        .iter()
+        .map(|(name, station)| format!("{name}={station}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn output(input: &[u8], config: &Config, format: Format) -> String {
+    let res = aggregate(input, config).unwrap();
+    let mut out = Vec::new();
+    write_results(&mut out, &res, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// Unique to this test process, as the tests of several files run at the same time
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("brc-{name}-{}", std::process::id()))
+}
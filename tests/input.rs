@@ -0,0 +1,105 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use brc::input::{Concat, decompress, is_compressed, shard_paths};
+use brc::{Config, Policy, aggregate};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::common::{config, render, temp_path};
+
+mod common;
+
+// Rejected lines are reported by the input they're in, counting the newline added after an input that
+// lacks one as part of that input
+#[test]
+fn offsets_per_input() {
+    let inputs: Vec<(Arc<Path>, &[u8])> = vec![
+        (Arc::from(Path::new("a.txt")), b"a;1.0\nbad\n"),
+        (Arc::from(Path::new("b.txt")), b"b;2.0\nb;3.0"),
+        (Arc::from(Path::new("c.txt")), b"oops\nc;4.0\n"),
+    ];
+    let concat = Concat::new(inputs);
+    let origins = concat.origins();
+    let config = Config {
+        policy: Policy::Collect(10),
+        ..config(1)
+    };
+    let mut res = aggregate(concat, &config).unwrap();
+    let located: Vec<_> = res
+        .rejected
+        .first
+        .iter_mut()
+        .map(|err| {
+            origins.locate(err);
+            err.to_string()
+        })
+        .collect();
+    assert_eq!(
+        located,
+        [
+            "invalid line at byte 6 of a.txt: missing separator",
+            "invalid line at byte 0 of c.txt: missing separator",
+        ]
+    );
+}
+
+#[test]
+fn shards_matching_nothing() {
+    let dir = temp_path("shards");
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(shard_paths(&dir).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(shard_paths(&dir.join("nothing-*.txt")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    std::fs::write(dir.join("s1.txt"), "a;1.0\n").unwrap();
+    std::fs::write(dir.join("s2.txt"), "b;2.0\n").unwrap();
+    assert_eq!(shard_paths(&dir.join("s*.txt")).unwrap(), [dir.join("s1.txt"), dir.join("s2.txt")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn read_all(data: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    decompress(io::Cursor::new(data)).unwrap().read_to_end(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn decompression() {
+    let data: Vec<u8> = (0..100_000).flat_map(|i| format!("s{};{}.5\n", i % 7, i % 50).into_bytes()).collect();
+    assert_eq!(read_all(gzip(&data)), data);
+    assert_eq!(read_all(zstd::encode_all(&data[..], 1).unwrap()), data);
+
+    // concatenated gzip members, as written by `cat a.gz b.gz`
+    let (first, second) = data.split_at(12_345);
+    let mut members = gzip(first);
+    members.extend(gzip(second));
+    assert_eq!(read_all(members), data);
+
+    // anything else is passed through, including input shorter than the magic bytes
+    assert_eq!(read_all(data.clone()), data);
+    assert_eq!(read_all(b"a;1".to_vec()), b"a;1");
+    assert_eq!(read_all(Vec::new()), b"");
+
+    let res = aggregate(decompress(io::Cursor::new(gzip(&data))).unwrap(), &config(3)).unwrap();
+    assert_eq!(render(&res), render(&aggregate(&data[..], &config(3)).unwrap()));
+}
+
+#[test]
+fn compressed_files() {
+    let dir = temp_path("compressed");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "a;1.0\n").unwrap();
+    std::fs::write(dir.join("a.txt.gz"), gzip(b"a;1.0\n")).unwrap();
+    std::fs::write(dir.join("a.txt.zst"), zstd::encode_all(&b"a;1.0\n"[..], 1).unwrap()).unwrap();
+    assert!(!is_compressed(&dir.join("a.txt")).unwrap());
+    assert!(is_compressed(&dir.join("a.txt.gz")).unwrap());
+    assert!(is_compressed(&dir.join("a.txt.zst")).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,107 @@
+use brc::output::{Format, write_histograms, write_results};
+use brc::{Config, Results, aggregate};
+
+use crate::common::{config, output};
+
+mod common;
+
+// Every format shows the same rounded mean as the text output, at the measurements' precision
+#[test]
+fn mean_matches_text() {
+    let input = b"a;1.0\na;1.0\na;1.1\nb;2.0\nc;-0.1\nc;0.0\n";
+    assert_eq!(output(input, &config(1), Format::Text), "{a=1.0/1.0/1.1, b=2.0/2.0/2.0, c=-0.1/0.0/0.0}\n");
+    assert_eq!(
+        output(input, &config(1), Format::Json),
+        "{\n  \
+         \"a\": {\"min\": 1.0, \"mean\": 1.0, \"max\": 1.1, \"count\": 3, \"sum\": 3.1},\n  \
+         \"b\": {\"min\": 2.0, \"mean\": 2.0, \"max\": 2.0, \"count\": 1, \"sum\": 2.0},\n  \
+         \"c\": {\"min\": -0.1, \"mean\": 0.0, \"max\": 0.0, \"count\": 2, \"sum\": -0.1}\n}\n"
+    );
+    assert_eq!(
+        output(input, &config(1), Format::Tsv),
+        "station\tmin\tmean\tmax\tcount\tsum\na\t1.0\t1.0\t1.1\t3\t3.1\nb\t2.0\t2.0\t2.0\t1\t2.0\nc\t-0.1\t0.0\t0.0\t2\t-0.1\n"
+    );
+}
+
+#[test]
+fn json_escaping() {
+    let input = "quote\"d;1.0\nback\\slash;2.0\ntab\there;3.0\nbell\u{7};4.0\nzürich;5.0\n".as_bytes();
+    assert_eq!(
+        output(input, &config(1), Format::Json),
+        "{\n  \
+         \"back\\\\slash\": {\"min\": 2.0, \"mean\": 2.0, \"max\": 2.0, \"count\": 1, \"sum\": 2.0},\n  \
+         \"bell\\u0007\": {\"min\": 4.0, \"mean\": 4.0, \"max\": 4.0, \"count\": 1, \"sum\": 4.0},\n  \
+         \"quote\\\"d\": {\"min\": 1.0, \"mean\": 1.0, \"max\": 1.0, \"count\": 1, \"sum\": 1.0},\n  \
+         \"tab\\there\": {\"min\": 3.0, \"mean\": 3.0, \"max\": 3.0, \"count\": 1, \"sum\": 3.0},\n  \
+         \"zürich\": {\"min\": 5.0, \"mean\": 5.0, \"max\": 5.0, \"count\": 1, \"sum\": 5.0}\n}\n"
+    );
+}
+
+// RFC 4180: fields with the delimiter or a quote are quoted, with quotes doubled
+#[test]
+fn csv_quoting() {
+    let input = b"plain;1.0\nwith,comma;2.0\nsay \"hi\";3.0\n";
+    assert_eq!(
+        output(input, &config(1), Format::Csv),
+        "station,min,mean,max,count,sum\nplain,1.0,1.0,1.0,1,1.0\n\"say \"\"hi\"\"\",3.0,3.0,3.0,1,3.0\n\
+         \"with,comma\",2.0,2.0,2.0,1,2.0\n"
+    );
+    // a comma doesn't need quoting in TSV
+    assert!(output(input, &config(1), Format::Tsv).contains("\nwith,comma\t2.0\t"));
+}
+
+// Every row has as many fields as the header, even for a station without the variance or percentiles
+#[test]
+fn rows_as_long_as_the_header() {
+    let full = Config {
+        variance: true,
+        percentiles: true,
+        ..config(1)
+    };
+    let mut res = aggregate(&b"a;1.0\na;3.0\n"[..], &full).unwrap();
+    res.stations.extend(aggregate(&b"b;2.0\n"[..], &config(1)).unwrap().stations);
+    let mut csv = Vec::new();
+    write_results(&mut csv, &res, Format::Csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "station,min,mean,max,count,sum,variance,stddev,median,p95,p99\n\
+         a,1.0,2.0,3.0,2,4.0,1,1,1.0,3.0,3.0\n\
+         b,2.0,2.0,2.0,1,2.0,,,,,\n"
+    );
+}
+
+fn histograms(res: &Results, format: Format) -> String {
+    let mut out = Vec::new();
+    write_histograms(&mut out, res, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// c is aggregated without percentiles, so it has no histogram
+#[test]
+fn histogram_formats() {
+    let percentiles = Config {
+        percentiles: true,
+        ..config(1)
+    };
+    let mut res = aggregate(&b"a;1.0\na;1.0\na;-2.5\nb,x;150.0\n"[..], &percentiles).unwrap();
+    res.stations.extend(aggregate(&b"c;3.0\n"[..], &config(1)).unwrap().stations);
+
+    assert_eq!(histograms(&res, Format::Text), "{a={-2.5=1, 1.0=2}, b,x={150.0=1}, c={}}\n");
+    assert_eq!(
+        histograms(&res, Format::Json),
+        "{\n  \"a\": {\"-2.5\": 1, \"1.0\": 2},\n  \"b,x\": {\"150.0\": 1},\n  \"c\": {}\n}\n"
+    );
+    assert_eq!(
+        histograms(&res, Format::Csv),
+        "station,measurement,count\na,-2.5,1\na,1.0,2\n\"b,x\",150.0,1\n"
+    );
+    assert_eq!(
+        histograms(&res, Format::Tsv),
+        "station\tmeasurement\tcount\na\t-2.5\t1\na\t1.0\t2\nb,x\t150.0\t1\n"
+    );
+
+    let empty = Results::default();
+    assert_eq!(histograms(&empty, Format::Text), "{}\n");
+    assert_eq!(histograms(&empty, Format::Json), "{}\n");
+    assert_eq!(histograms(&empty, Format::Csv), "station,measurement,count\n");
+}
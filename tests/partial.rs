@@ -0,0 +1,76 @@
+use brc::output::{Format, write_results};
+use brc::partial::{merge, read_partial, write_partial};
+use brc::{Config, Results, aggregate};
+
+use crate::common::{config, render};
+
+mod common;
+
+fn stats(variance: bool, percentiles: bool) -> Config {
+    Config {
+        variance,
+        percentiles,
+        ..config(1)
+    }
+}
+
+// Through the partial file format and back
+fn roundtrip(input: &[u8], config: &Config) -> Results {
+    let mut partial = Vec::new();
+    write_partial(&mut partial, &aggregate(input, config).unwrap()).unwrap();
+    read_partial(&partial[..]).unwrap()
+}
+
+#[test]
+fn merge_matches_single_run() {
+    // a few measurements beyond 99.9 make some histograms sparse
+    let lines = (0..3_000).map(|i| format!("station {};{}.{}\n", i % 7, i % 300 - 150, i % 10));
+    let lines = lines.collect::<Vec<_>>();
+    let config = stats(true, true);
+
+    let single = aggregate(lines.concat().as_bytes(), &config).unwrap();
+    let merged = merge(lines.chunks(1_100).map(|part| roundtrip(part.concat().as_bytes(), &config))).unwrap();
+    assert_eq!(render(&merged), render(&single));
+    assert_eq!(merged.stations.len(), 7);
+}
+
+// Whatever only one side of a merge tracked is dropped, no matter the order
+#[test]
+fn merge_with_different_options() {
+    let full = || roundtrip(b"a;1.0\na;3.0\n", &stats(true, true));
+    let plain = || roundtrip(b"a;2.0\n", &stats(false, false));
+    assert_eq!(render(&merge([full(), plain()]).unwrap()), "a=1.0/2.0/3.0");
+    assert_eq!(render(&merge([plain(), full()]).unwrap()), "a=1.0/2.0/3.0");
+}
+
+// Also for stations that are only in one of them, so that every station has the same statistics
+#[test]
+fn merge_different_stations_with_different_options() {
+    let full = roundtrip(b"a;1.0\na;3.0\n", &stats(true, true));
+    let plain = roundtrip(b"b;2.0\n", &stats(false, false));
+    let merged = merge([full, plain]).unwrap();
+    assert_eq!(render(&merged), "a=1.0/2.0/3.0, b=2.0/2.0/2.0");
+    let mut csv = Vec::new();
+    write_results(&mut csv, &merged, Format::Csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "station,min,mean,max,count,sum\na,1.0,2.0,3.0,2,4.0\nb,2.0,2.0,2.0,1,2.0\n"
+    );
+
+    let variance = roundtrip(b"a;1.0\na;3.0\n", &stats(true, false));
+    let both = roundtrip(b"b;2.0\n", &stats(true, true));
+    assert_eq!(render(&merge([variance, both]).unwrap()), "a=1.0/2.0/3.0/1.0, b=2.0/2.0/2.0/0.0");
+}
+
+// 1.5 with two decimals is 150, which can't be added to 1.5 with one
+#[test]
+fn merge_with_different_decimals() {
+    let mut hundredths = stats(false, false);
+    hundredths.schema.decimals = Some(2);
+    let parts = [roundtrip(b"a;1.5\n", &stats(false, false)), roundtrip(b"b;1.5\n", &hundredths)];
+    let err = merge(parts).err().unwrap();
+    assert_eq!(err.to_string(), "partials were made with different --decimals");
+
+    let parts = [roundtrip(b"a;1.5\n", &hundredths), roundtrip(b"a;2.25\n", &hundredths)];
+    assert_eq!(render(&merge(parts).unwrap()), "a=1.50/1.88/2.25");
+}
@@ -0,0 +1,119 @@
+use brc::{Columns, Config, Error, ParseErrorKind, Policy, Schema, aggregate};
+
+use crate::common::{config, render};
+
+mod common;
+
+fn with_schema(schema: Schema) -> Config {
+    Config {
+        policy: Policy::Collect(10),
+        schema,
+        ..config(1)
+    }
+}
+
+fn columns(station: usize, measurement: usize) -> Schema {
+    Schema {
+        columns: Some(Columns {
+            station,
+            measurement,
+            timestamp: None,
+        }),
+        ..Schema::default()
+    }
+}
+
+#[test]
+fn columns_in_any_order() {
+    let input = b"x;1.5;a;y\nx;2.5;b;y;z\nx;-1.0;a\n";
+    let res = aggregate(&input[..], &with_schema(columns(2, 1))).unwrap();
+    assert_eq!(render(&res), "a=-1.0/0.3/1.5, b=2.5/2.5/2.5");
+    assert_eq!(res.rejected.count, 0);
+}
+
+#[test]
+fn too_few_columns() {
+    let input = b"1.0;a\n2.0\n;\n3.0;b;extra\n";
+    let res = aggregate(&input[..], &with_schema(columns(1, 0))).unwrap();
+    assert_eq!(render(&res), "a=1.0/1.0/1.0, b=3.0/3.0/3.0");
+    let kinds: Vec<_> = res.rejected.first.iter().map(|err| (err.offset, err.kind)).collect();
+    assert_eq!(kinds, [(6, ParseErrorKind::MissingColumn), (10, ParseErrorKind::InvalidMeasurement)]);
+    assert_eq!(ParseErrorKind::MissingColumn.to_string(), "fewer columns than the schema");
+
+    let fail_fast = Config {
+        policy: Policy::FailFast,
+        ..with_schema(columns(0, 1))
+    };
+    let err = aggregate(&b"a\n"[..], &fail_fast);
+    assert!(matches!(err, Err(Error::Parse(err)) if err.kind == ParseErrorKind::MissingColumn));
+}
+
+#[test]
+fn tab_delimiter() {
+    let schema = Schema {
+        delimiter: b'\t',
+        ..columns(0, 2)
+    };
+    let input = b"a\tignored;1.0\t1.5\nb c\t\t-2.0\nno tabs;1.0\n";
+    let res = aggregate(&input[..], &with_schema(schema)).unwrap();
+    assert_eq!(render(&res), "a=1.5/1.5/1.5, b c=-2.0/-2.0/-2.0");
+    assert_eq!(res.rejected.first[0].kind, ParseErrorKind::MissingColumn);
+
+    // without columns, the last tab separates the station from the measurement
+    let schema = Schema {
+        delimiter: b'\t',
+        ..Schema::default()
+    };
+    let res = aggregate(&b"a;b\t1.0\n"[..], &with_schema(schema)).unwrap();
+    assert_eq!(render(&res), "a;b=1.0/1.0/1.0");
+}
+
+// A single measurement as the station shows it, or why it was rejected
+fn measurement(s: &str, decimals: u8) -> Result<String, ParseErrorKind> {
+    let config = Config {
+        policy: Policy::FailFast,
+        ..with_schema(Schema {
+            decimals: Some(decimals),
+            ..Schema::default()
+        })
+    };
+    match aggregate(format!("a;{s}\n").as_bytes(), &config) {
+        Ok(res) => Ok(res.stations[0].1.to_string().split('/').next().unwrap().to_string()),
+        Err(Error::Parse(err)) => Err(err.kind),
+        Err(err) => panic!("{err}"),
+    }
+}
+
+#[test]
+fn decimals() {
+    assert_eq!(measurement("12", 1), Ok("12.0".into()));
+    assert_eq!(measurement("+5.0", 1), Ok("5.0".into()));
+    assert_eq!(measurement("-0.25", 2), Ok("-0.25".into()));
+    assert_eq!(measurement("-0.0", 2), Ok("0.00".into()));
+    assert_eq!(measurement("0.5", 3), Ok("0.500".into()));
+    for s in ["12.", ".5", "+-1", "-+1", "--1", "", "-", "+", "1.2.3", "1,5", " 1.0"] {
+        assert_eq!(measurement(s, 2), Err(ParseErrorKind::InvalidMeasurement), "{s:?}");
+    }
+    assert_eq!(measurement("-0.25", 1), Err(ParseErrorKind::TooManyDecimals));
+    assert_eq!(measurement("1.000", 2), Err(ParseErrorKind::TooManyDecimals));
+}
+
+#[test]
+fn no_decimals() {
+    assert_eq!(measurement("12", 0), Ok("12".into()));
+    assert_eq!(measurement("-3", 0), Ok("-3".into()));
+    assert_eq!(measurement("1.5", 0), Err(ParseErrorKind::TooManyDecimals));
+    assert_eq!(measurement("1.", 0), Err(ParseErrorKind::InvalidMeasurement));
+}
+
+// Measurements are kept as i32 after scaling by 10^decimals
+#[test]
+fn overflow_after_scaling() {
+    assert_eq!(measurement("2147483647", 0), Ok("2147483647".into()));
+    assert_eq!(measurement("2147483648", 0), Err(ParseErrorKind::InvalidMeasurement));
+    assert_eq!(measurement("2147", 6), Ok("2147.000000".into()));
+    assert_eq!(measurement("-2147.483647", 6), Ok("-2147.483647".into()));
+    assert_eq!(measurement("2148", 6), Err(ParseErrorKind::InvalidMeasurement));
+    assert_eq!(measurement("2147.483648", 6), Err(ParseErrorKind::InvalidMeasurement));
+    assert_eq!(measurement("21474836.48", 2), Err(ParseErrorKind::InvalidMeasurement));
+}
@@ -0,0 +1,144 @@
+use brc::output::Format;
+use brc::{Columns, Config, Error, ParseErrorKind, Schema, Window, aggregate};
+
+use crate::common::{config, output};
+
+mod common;
+
+fn windowed(window: Window) -> Config {
+    Config {
+        window: Some(window),
+        ..config(1)
+    }
+}
+
+fn window_starts(input: &[u8], window: Window) -> Vec<i64> {
+    aggregate(input, &windowed(window)).unwrap().windows.iter().map(|(start, _)| *start).collect()
+}
+
+fn rejects(timestamp: &str) -> bool {
+    let line = format!("a;{timestamp};1.0\n");
+    match aggregate(line.as_bytes(), &windowed(Window::Day)) {
+        Err(Error::Parse(err)) => err.kind == ParseErrorKind::InvalidTimestamp,
+        _ => false,
+    }
+}
+
+#[test]
+fn leap_days() {
+    assert!(!rejects("2024-02-29T12:00:00Z"));
+    assert!(!rejects("2000-02-29T12:00:00Z"));
+    assert!(!rejects("1600-02-29T12:00:00Z"));
+    assert!(rejects("2023-02-29T12:00:00Z"));
+    assert!(rejects("1900-02-29T12:00:00Z"));
+    assert!(rejects("2024-04-31T12:00:00Z"));
+    assert_eq!(window_starts(b"a;2024-02-29T12:00:00Z;1.0\n", Window::Day), [1_709_164_800]);
+    assert_eq!(window_starts(b"a;2024-03-01T00:00:00Z;1.0\n", Window::Day), [1_709_251_200]);
+}
+
+#[test]
+fn invalid_timestamps() {
+    for timestamp in ["", "-", "12a", "2024-06-01T24:00:00Z", "2024-13-01T00:00:00Z", "2024-06-01 00:00:00Z"] {
+        assert!(rejects(timestamp), "{timestamp}");
+    }
+}
+
+// Windows before the epoch start at or before the timestamp too, not rounded towards zero
+#[test]
+fn before_the_epoch() {
+    let input = b"a;1969-12-31T23:59:59Z;1.0\na;-1;2.0\na;-3600;3.0\na;-3601;4.0\n";
+    assert_eq!(window_starts(input, Window::Hour), [-7_200, -3_600]);
+    assert_eq!(window_starts(input, Window::Day), [-86_400]);
+    assert_eq!(window_starts(b"a;1600-03-01T00:00:00Z;1.0\n", Window::Day), [-11_670_912_000]);
+    assert_eq!(
+        output(input, &windowed(Window::Hour), Format::Text),
+        "1969-12-31T22:00:00Z {a=4.0/4.0/4.0}\n1969-12-31T23:00:00Z {a=1.0/2.0/3.0}\n"
+    );
+}
+
+// The start of the window of the earliest timestamps doesn't fit in an i64
+#[test]
+fn window_before_i64_min() {
+    let input = b"a;-9223372036854775807;1.0\n";
+    for window in [Window::Hour, Window::Day] {
+        let schemas = [
+            Schema::default(),
+            Schema {
+                columns: Some(Columns {
+                    station: 0,
+                    measurement: 2,
+                    timestamp: Some(1),
+                }),
+                ..Schema::default()
+            },
+        ];
+        for schema in schemas {
+            let config = Config {
+                schema,
+                ..windowed(window)
+            };
+            match aggregate(&input[..], &config) {
+                Err(Error::Parse(err)) => assert_eq!(err.kind, ParseErrorKind::InvalidTimestamp),
+                _ => panic!("{window:?} {schema:?}"),
+            }
+        }
+    }
+    assert_eq!(window_starts(b"a;-9223372036800000000;1.0\n", Window::Hour), [-9_223_372_036_800_000_000]);
+}
+
+#[test]
+fn boundaries() {
+    let input = b"a;2024-06-01T12:59:59Z;1.0\na;2024-06-01T13:00:00Z;2.0\n\
+                  a;2024-06-01T23:59:59.999Z;3.0\na;1717286400;4.0\n";
+    assert_eq!(
+        output(input, &windowed(Window::Hour), Format::Text),
+        "2024-06-01T12:00:00Z {a=1.0/1.0/1.0}\n2024-06-01T13:00:00Z {a=2.0/2.0/2.0}\n\
+         2024-06-01T23:00:00Z {a=3.0/3.0/3.0}\n2024-06-02T00:00:00Z {a=4.0/4.0/4.0}\n"
+    );
+    assert_eq!(
+        output(input, &windowed(Window::Day), Format::Text),
+        "2024-06-01T00:00:00Z {a=1.0/2.0/3.0}\n2024-06-02T00:00:00Z {a=4.0/4.0/4.0}\n"
+    );
+}
+
+#[test]
+fn window_outputs() {
+    let input = b"b;2024-06-01T10:15:00Z;1.0\na;2024-06-01T10:45:00Z;2.0\na;2024-06-01T11:00:00Z;3.0\n";
+    let config = windowed(Window::Hour);
+    assert_eq!(
+        output(input, &config, Format::Csv),
+        "window,station,min,mean,max,count,sum\n\
+         2024-06-01T10:00:00Z,a,2.0,2.0,2.0,1,2.0\n\
+         2024-06-01T10:00:00Z,b,1.0,1.0,1.0,1,1.0\n\
+         2024-06-01T11:00:00Z,a,3.0,3.0,3.0,1,3.0\n"
+    );
+    assert_eq!(
+        output(input, &config, Format::Json),
+        "{\n  \
+         \"2024-06-01T10:00:00Z\": {\n    \
+         \"a\": {\"min\": 2.0, \"mean\": 2.0, \"max\": 2.0, \"count\": 1, \"sum\": 2.0},\n    \
+         \"b\": {\"min\": 1.0, \"mean\": 1.0, \"max\": 1.0, \"count\": 1, \"sum\": 1.0}\n  \
+         },\n  \
+         \"2024-06-01T11:00:00Z\": {\n    \
+         \"a\": {\"min\": 3.0, \"mean\": 3.0, \"max\": 3.0, \"count\": 1, \"sum\": 3.0}\n  \
+         }\n}\n"
+    );
+}
+
+// Per window histograms start out sparse, and still give the same percentiles
+#[test]
+fn window_percentiles() {
+    let config = Config {
+        percentiles: true,
+        ..windowed(Window::Day)
+    };
+    let input = b"a;0;1.0\na;1;2.0\na;2;150.0\na;86400;-3.5\n";
+    assert_eq!(
+        output(input, &config, Format::Text),
+        "1970-01-01T00:00:00Z {a=1.0/51.0/150.0/2.0/150.0/150.0}\n\
+         1970-01-02T00:00:00Z {a=-3.5/-3.5/-3.5/-3.5/-3.5/-3.5}\n"
+    );
+    let res = aggregate(&input[..], &config).unwrap();
+    // and so do the totals over all windows
+    assert_eq!(res.stations[0].1.to_string(), "-3.5/37.4/150.0/1.0/150.0/150.0");
+}